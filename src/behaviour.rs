@@ -0,0 +1,151 @@
+use nannou_osc as osc;
+
+use crate::scraen::expression::Expression;
+use crate::vision::Scene;
+
+// how long the eyes stay surprised after someone shows up
+const SURPRISE_SEC: f64 = 1.5;
+// nobody around for this long and the eyes get sleepy
+const SLEEPY_AFTER_SEC: f64 = 30.0;
+// default time for the lids to get from one expression to the next
+pub const TRANSITION_SEC: f64 = 0.4;
+const SURPRISE_TRANSITION_SEC: f64 = 0.1;
+const SLEEPY_TRANSITION_SEC: f64 = 3.0;
+
+// Picks the wall's expression from what the camera sees, unless something
+// on the network has taken over. Over OSC:
+//
+//   /eyes/expression surprised      for TRANSITION_SEC
+//   /eyes/expression sleepy 2.0     over 2 seconds
+//   /eyes/expression auto           back to the camera
+pub struct Behaviour {
+    osc: Option<osc::Receiver>,
+    // set over OSC, holds until the next message
+    held: Option<Expression>,
+    current: Expression,
+    // when current started
+    since: f64,
+    // when a face was last seen
+    last_face: f64,
+    had_face: bool,
+}
+
+// a change for every panel, with how long it takes
+pub struct Change {
+    pub expression: Expression,
+    pub duration: f64,
+}
+
+impl Behaviour {
+    pub fn new(osc_port: u16) -> Behaviour {
+        let osc = match osc::receiver(osc_port) {
+            Ok(receiver) => Some(receiver),
+            Err(err) => {
+                println!("not listening for OSC on port {}: {}", osc_port, err);
+                None
+            }
+        };
+        Behaviour {
+            osc,
+            held: None,
+            current: Expression::Neutral,
+            since: 0.0,
+            last_face: 0.0,
+            had_face: false,
+        }
+    }
+
+    // what every panel should be showing, for panels built after the change
+    pub fn current(&self) -> Expression {
+        self.current
+    }
+
+    pub fn update(&mut self, scene: &Scene, time: f64) -> Option<Change> {
+        let has_face = scene.face_size > 0.0;
+        let new_face = has_face && !self.had_face;
+        self.had_face = has_face;
+        if has_face {
+            self.last_face = time;
+        }
+
+        if let Some(change) = self.poll_osc() {
+            return self.change(change.expression, change.duration, time);
+        }
+        if self.held.is_some() {
+            return None;
+        }
+
+        if new_face {
+            return self.change(Expression::Surprised, SURPRISE_TRANSITION_SEC, time);
+        }
+        match self.current {
+            Expression::Surprised if time - self.since > SURPRISE_SEC => {
+                self.change(Expression::Neutral, TRANSITION_SEC, time)
+            }
+            Expression::Neutral if time - self.last_face > SLEEPY_AFTER_SEC => {
+                self.change(Expression::Sleepy, SLEEPY_TRANSITION_SEC, time)
+            }
+            _ => None,
+        }
+    }
+
+    fn change(&mut self, expression: Expression, duration: f64, time: f64) -> Option<Change> {
+        if expression == self.current {
+            return None;
+        }
+        self.current = expression;
+        self.since = time;
+        Some(Change {
+            expression,
+            duration,
+        })
+    }
+
+    // the last expression message since the previous frame
+    fn poll_osc(&mut self) -> Option<Change> {
+        let receiver = self.osc.as_ref()?;
+        let mut change = None;
+        for (packet, _addr) in receiver.try_iter() {
+            for msg in packet.into_msgs() {
+                if msg.addr != "/eyes/expression" {
+                    continue;
+                }
+                let args = msg.args.unwrap_or_default();
+                let name = match args.first() {
+                    Some(osc::Type::String(name)) => name.clone(),
+                    _ => {
+                        println!("/eyes/expression needs a name");
+                        continue;
+                    }
+                };
+                let duration = match args.get(1) {
+                    Some(osc::Type::Float(sec)) if sec.is_finite() && *sec >= 0.0 => *sec as f64,
+                    Some(osc::Type::Float(sec)) => {
+                        println!("/eyes/expression can't take {} seconds", sec);
+                        continue;
+                    }
+                    _ => TRANSITION_SEC,
+                };
+                if name == "auto" {
+                    self.held = None;
+                    change = Some(Change {
+                        expression: Expression::Neutral,
+                        duration,
+                    });
+                    continue;
+                }
+                match name.parse() {
+                    Ok(expression) => {
+                        self.held = Some(expression);
+                        change = Some(Change {
+                            expression,
+                            duration,
+                        });
+                    }
+                    Err(err) => println!("{}", err),
+                }
+            }
+        }
+        change
+    }
+}
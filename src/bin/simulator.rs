@@ -0,0 +1,560 @@
+// Virtual LED panels. Decodes the serial byte stream the way the controller
+// firmware does and draws every panel from the bytes it latched, so wiring,
+// rotation and colour bugs show up without hardware.
+//
+//   simulator                      opens a pseudo-terminal and prints its path,
+//                                  point PORT_NAME at it and run eyes
+//   simulator --port <path>        reads an existing serial device or pty
+//   simulator --recording <file>   plays back a recording from `RECORD_TO`
+//   --mapping <serpentine|rows|columns|table file>
+//                                  how the panels are wired, serpentine by default
+//   --config <file>                each panel's mapping and orientation from an
+//                                  eyes config, --mapping and upright for the rest
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use nannou::prelude::*;
+use serde::Deserialize;
+use serial2::SerialPort;
+
+use eyes::connection::protocol::{self, DecodeError, Decoder, Kind, Packet};
+use eyes::recording::format as recording;
+use eyes::scraen::color::ColorFormat;
+use eyes::scraen::mapping::{Lines, PixelMapping, Serpentine};
+use eyes::scraen::orientation::Orientation;
+
+const WINDOW_WH: (u32, u32) = (900, 400);
+// accepted frames kept per panel as delta bases
+const HISTORY: usize = 8;
+const USAGE: &str = "usage: simulator [--port <path> | --recording <file>] \
+[--mapping <serpentine|rows|columns|table file>] [--config <file>]";
+
+enum Source {
+    Pty,
+    Port(String),
+    Recording(String),
+}
+
+struct Args {
+    source: Source,
+    // for panels the config doesn't list
+    mapping: PixelMapping,
+    mounts: HashMap<u8, Mount>,
+}
+
+// how one panel is wired and hung
+struct Mount {
+    mapping: PixelMapping,
+    orientation: Orientation,
+}
+
+// the parts of an eyes config the simulator needs, the rest is ignored
+#[derive(Deserialize)]
+struct SimConfig {
+    scraens: Vec<SimScraen>,
+}
+
+#[derive(Deserialize)]
+struct SimScraen {
+    output: SimOutput,
+    #[serde(default)]
+    orientation: Orientation,
+    #[serde(default = "serpentine_rows")]
+    mapping: PixelMapping,
+}
+
+#[derive(Deserialize)]
+struct SimOutput {
+    address: u16,
+}
+
+enum Msg {
+    Packet(Packet),
+    Error(DecodeError),
+    Rejected(String),
+}
+
+// eyes opens `path`, the simulator reads the master
+struct Pty {
+    master: File,
+    slave: File,
+    path: String,
+}
+
+// the part of the firmware that unpacks compressed panel packets
+struct Firmware {
+    history: HashMap<u8, VecDeque<(u16, Vec<u8>)>>,
+}
+
+struct SimPanel {
+    wh: (u8, u8),
+    format: ColorFormat,
+    orientation: Orientation,
+    table: Vec<Option<usize>>,
+    // received since the last present
+    pending: Option<Vec<u8>>,
+    // what the LEDs show
+    shown: Vec<u8>,
+    packets: u32,
+}
+
+struct Model {
+    messages: Receiver<Msg>,
+    mapping: PixelMapping,
+    mounts: HashMap<u8, Mount>,
+    panels: BTreeMap<u8, SimPanel>,
+    frames: u32,
+    errors: u32,
+    last_error: Option<String>,
+}
+
+fn main() -> Result<()> {
+    // checked before the window opens, model parses them again
+    parse_args()?;
+    nannou::app(model).update(update).run();
+    Ok(())
+}
+
+// the same wiring as the panels in the default config
+fn serpentine_rows() -> PixelMapping {
+    PixelMapping::Lines(Lines {
+        serpentine: Serpentine::EvenReversed,
+        gap: 1,
+        ..Lines::ROW_MAJOR
+    })
+}
+
+fn parse_args() -> Result<Args> {
+    let mut source = Source::Pty;
+    let mut mapping = serpentine_rows();
+    let mut mounts = HashMap::new();
+
+    let usage = |msg: String| anyhow!("{}\n{}", msg, USAGE);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| usage(format!("{} needs a value", arg)))?;
+        match arg.as_str() {
+            "--port" => source = Source::Port(value),
+            "--recording" => source = Source::Recording(value),
+            "--mapping" => {
+                mapping = match value.as_str() {
+                    "serpentine" => serpentine_rows(),
+                    "rows" => PixelMapping::Lines(Lines::ROW_MAJOR),
+                    "columns" => PixelMapping::Lines(Lines::COLUMN_MAJOR),
+                    path => PixelMapping::Table(path.to_owned().into()),
+                }
+            }
+            "--config" => mounts = load_mounts(&value)?,
+            other => return Err(usage(format!("unknown argument {}", other))),
+        }
+    }
+    Ok(Args {
+        source,
+        mapping,
+        mounts,
+    })
+}
+
+// panels on addresses past a byte only go out over the network, so they
+// never reach the simulator
+fn load_mounts(path: &str) -> Result<HashMap<u8, Mount>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading config {}", path))?;
+    let config: SimConfig = toml::from_str(&text).with_context(|| format!("parsing {}", path))?;
+    Ok(config
+        .scraens
+        .into_iter()
+        .filter_map(|scraen| {
+            let address = u8::try_from(scraen.output.address).ok()?;
+            let mount = Mount {
+                mapping: scraen.mapping,
+                orientation: scraen.orientation,
+            };
+            Some((address, mount))
+        })
+        .collect())
+}
+
+fn model(app: &App) -> Model {
+    app.new_window()
+        .size(WINDOW_WH.0, WINDOW_WH.1)
+        .title("eyes simulator")
+        .view(view)
+        .build()
+        .unwrap();
+
+    let args = parse_args().expect("checked in main");
+    let (tx, rx) = channel();
+    match args.source {
+        Source::Pty => {
+            let Pty {
+                master,
+                slave,
+                path,
+            } = open_pty().expect("couldn't open a pseudo-terminal");
+            println!("simulating panels on {}", path);
+            let reply = master.try_clone().unwrap();
+            thread::spawn(move || {
+                read_stream(master, Some(reply), tx);
+                drop(slave);
+            });
+        }
+        Source::Port(path) => {
+            let mut port = SerialPort::open(&path, 115200).expect("couldn't open port");
+            port.set_read_timeout(Duration::from_secs(3600)).unwrap();
+            thread::spawn(move || read_stream(PortReader(port), None, tx));
+        }
+        Source::Recording(path) => {
+            thread::spawn(move || play_recording(&path, tx));
+        }
+    }
+
+    Model {
+        messages: rx,
+        mapping: args.mapping,
+        mounts: args.mounts,
+        panels: BTreeMap::new(),
+        frames: 0,
+        errors: 0,
+        last_error: None,
+    }
+}
+
+// what the firmware does with each packet
+fn update(_app: &App, model: &mut Model, _update: Update) {
+    for msg in model.messages.try_iter() {
+        let packet = match msg {
+            Msg::Packet(packet) => packet,
+            Msg::Error(err) => {
+                model.errors += 1;
+                model.last_error = Some(err.to_string());
+                continue;
+            }
+            Msg::Rejected(reason) => {
+                model.errors += 1;
+                model.last_error = Some(reason);
+                continue;
+            }
+        };
+
+        let h = packet.header;
+        match h.kind {
+            Kind::Panel => {
+                let format = match ColorFormat::from_byte(h.format) {
+                    Some(f) => f,
+                    None => {
+                        model.errors += 1;
+                        model.last_error = Some(format!("unknown colour format {}", h.format));
+                        continue;
+                    }
+                };
+                let mount = model.mounts.get(&h.panel);
+                let mapping = mount.map_or(&model.mapping, |mount| &mount.mapping);
+                let orientation = mount.map_or(Orientation::UPRIGHT, |mount| mount.orientation);
+                let panel = model.panels.entry(h.panel).or_insert_with(|| SimPanel {
+                    wh: (0, 0),
+                    format,
+                    orientation,
+                    table: Vec::new(),
+                    pending: None,
+                    shown: Vec::new(),
+                    packets: 0,
+                });
+                if panel.wh != (h.width, h.height) {
+                    panel.wh = (h.width, h.height);
+                    panel.table = mapping
+                        .table((h.width as u32, h.height as u32))
+                        .unwrap_or_else(|err| {
+                            model.last_error = Some(format!("{:#}", err));
+                            Vec::new()
+                        });
+                }
+                panel.format = format;
+                panel.pending = Some(packet.payload);
+                panel.packets += 1;
+            }
+            Kind::Present => {
+                for panel in model.panels.values_mut() {
+                    if let Some(pending) = panel.pending.take() {
+                        panel.shown = pending;
+                    }
+                }
+                model.frames += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn view(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+    draw.background().color(BLACK);
+    let win = app.window_rect();
+
+    let count = model.panels.len().max(1) as f32;
+    let slot_w = win.w() / count;
+    let slot_h = win.h() - 80.0;
+
+    for (i, (address, panel)) in model.panels.iter().enumerate() {
+        let (w, h) = (panel.wh.0 as usize, panel.wh.1 as usize);
+        if w == 0 || h == 0 {
+            continue;
+        }
+        // the wiring is a quarter turn from the panel's own frame, see
+        // Scraen::serial_packet, so it is w high and h wide
+        let frame_wh = (h as f32, w as f32);
+        let orientation = panel.orientation;
+        let (foot_w, foot_h) = orientation.footprint(frame_wh);
+        let cell = (slot_w * 0.8 / foot_w).min(slot_h * 0.8 / foot_h);
+        let center = vec2(win.left() + slot_w * (i as f32 + 0.5), 20.0);
+        let turn = Mat2::from_angle(orientation.angle());
+
+        let bpp = panel.format.bytes_per_pixel();
+        let mut pixels = vec![[0u8; 3]; w * h];
+        for (slot, target) in panel.table.iter().enumerate() {
+            let bytes = panel.shown.get(slot * bpp..(slot + 1) * bpp);
+            if let (Some(index), Some(bytes)) = (target, bytes) {
+                if let Some(pixel) = pixels.get_mut(*index) {
+                    *pixel = panel.format.unpack(bytes);
+                }
+            }
+        }
+
+        for (index, [r, g, b]) in pixels.iter().enumerate() {
+            // back to the panel's frame, then hung the way the config says
+            let (x, y) = ((index / w) as f32, (w - 1 - index % w) as f32);
+            let xy = vec2(x + 0.5 - frame_wh.0 / 2.0, frame_wh.1 / 2.0 - (y + 0.5));
+            draw.rect()
+                .xy(center + turn * (xy * orientation.mirror_signs()) * cell)
+                .w_h(cell * 0.9, cell * 0.9)
+                .color(rgb8(*r, *g, *b));
+        }
+
+        let expected = panel.table.len() * bpp;
+        let mut label = format!(
+            "panel {}  {}x{} {:?}\n{} packets",
+            address, w, h, panel.format, panel.packets
+        );
+        if panel.shown.len() != expected {
+            label += &format!("\n{} bytes, mapping wants {}", panel.shown.len(), expected);
+        }
+        draw.text(&label)
+            .color(WHITE)
+            .font_size(12)
+            .w(slot_w)
+            .xy(vec2(center.x, center.y - foot_h * cell / 2.0 - 30.0));
+    }
+
+    let mut status = format!("{} frames  {} bad packets", model.frames, model.errors);
+    if let Some(err) = &model.last_error {
+        status += &format!("  last: {}", err);
+    }
+    draw.text(&status)
+        .color(GREY)
+        .font_size(12)
+        .w(win.w())
+        .x_y(0.0, win.top() - 20.0);
+
+    draw.to_frame(app, &frame).unwrap();
+}
+
+impl Firmware {
+    fn new() -> Firmware {
+        Firmware {
+            history: HashMap::new(),
+        }
+    }
+
+    // turns rle and delta packets into plain panel packets, Err means NAK
+    fn accept(&mut self, mut packet: Packet) -> Result<Packet, String> {
+        let h = packet.header;
+        if !matches!(h.kind, Kind::Panel | Kind::PanelRle | Kind::PanelDelta) {
+            return Ok(packet);
+        }
+        let bpp = ColorFormat::from_byte(h.format)
+            .ok_or_else(|| format!("unknown colour format {}", h.format))?
+            .bytes_per_pixel();
+        let history = self.history.entry(h.panel).or_default();
+
+        let pixels = match h.kind {
+            Kind::PanelRle => protocol::rle_decode(&packet.payload, bpp)
+                .ok_or_else(|| format!("bad rle payload for panel {}", h.panel))?,
+            Kind::PanelDelta => {
+                let base_seq = protocol::delta_base(&packet.payload)
+                    .ok_or_else(|| format!("short delta for panel {}", h.panel))?;
+                let (_, base) = history
+                    .iter()
+                    .find(|(seq, _)| *seq == base_seq)
+                    .ok_or_else(|| format!("panel {} has no frame {}", h.panel, base_seq))?;
+                protocol::delta_apply(base, &packet.payload, bpp)
+                    .ok_or_else(|| format!("bad delta for panel {}", h.panel))?
+            }
+            _ => packet.payload,
+        };
+
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back((h.seq, pixels.clone()));
+
+        packet.header.kind = Kind::Panel;
+        packet.payload = pixels;
+        Ok(packet)
+    }
+}
+
+// reads bytes until the stream closes, answering panel packets like the firmware
+fn read_stream(mut stream: impl Read, mut reply: Option<File>, tx: Sender<Msg>) {
+    let mut firmware = Firmware::new();
+    let mut decoder = Decoder::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match stream.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(err) => {
+                println!("stream closed: {}", err);
+                return;
+            }
+        };
+        decoder.push(&buf[..n]);
+
+        while let Some(result) = decoder.next_packet() {
+            let msg = match result {
+                Ok(packet) => {
+                    let h = packet.header;
+                    let accepted = firmware.accept(packet);
+                    if let (Some(reply), true) = (&mut reply, h.kind != Kind::Present) {
+                        let kind = if accepted.is_ok() {
+                            Kind::Ack
+                        } else {
+                            Kind::Nak
+                        };
+                        let answer = Packet::reply(kind, h.panel, h.seq, Vec::new());
+                        let _ = reply.write_all(&answer.encode());
+                    }
+                    match accepted {
+                        Ok(packet) => Msg::Packet(packet),
+                        Err(reason) => Msg::Rejected(reason),
+                    }
+                }
+                Err(err) => Msg::Error(err),
+            };
+            if tx.send(msg).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// re-encodes each recorded panel so it goes through the same decoder as live bytes
+fn play_recording(path: &str, tx: Sender<Msg>) {
+    let reader = match recording::LogReader::open(path) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("couldn't open {}: {}", path, err);
+            return;
+        }
+    };
+
+    let start = Instant::now();
+    let mut decoder = Decoder::new();
+    let mut seq: u16 = 0;
+    for record in reader {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                println!("recording ended early: {}", err);
+                return;
+            }
+        };
+        let due = Duration::from_micros(record.time_us);
+        if due > start.elapsed() {
+            thread::sleep(due - start.elapsed());
+        }
+
+        let packet = match record.entry {
+            recording::Entry::Panel {
+                address,
+                wh,
+                format,
+                data,
+            } => match u8::try_from(address) {
+                Ok(address) => Packet::panel(address, wh, format, seq, data),
+                Err(_) => {
+                    println!("skipping panel {}, serial addresses stop at 255", address);
+                    continue;
+                }
+            },
+            recording::Entry::Present => Packet::present(seq),
+        };
+        seq = seq.wrapping_add(1);
+
+        decoder.push(&packet.encode());
+        while let Some(result) = decoder.next_packet() {
+            let msg = match result {
+                Ok(packet) => Msg::Packet(packet),
+                Err(err) => Msg::Error(err),
+            };
+            if tx.send(msg).is_err() {
+                return;
+            }
+        }
+    }
+    println!("recording finished");
+}
+
+struct PortReader(SerialPort);
+
+impl Read for PortReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+fn open_pty() -> std::io::Result<Pty> {
+    use std::ffi::CStr;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // closed on every return from here on
+    let master = unsafe { OwnedFd::from_raw_fd(fd) };
+    let fd = master.as_raw_fd();
+    unsafe {
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    let name = unsafe { libc::ptsname(fd) };
+    if name.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    let path = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+
+    // with no slave open a read on the master fails with EIO, which happens
+    // every time eyes closes the port. holding one open keeps the master
+    // readable across eyes restarting or reconnecting
+    let slave = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path)?;
+
+    Ok(Pty {
+        master: File::from(master),
+        slave,
+        path,
+    })
+}
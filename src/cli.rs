@@ -0,0 +1,74 @@
+use clap::{Parser, Subcommand};
+
+use crate::scraen::TestPattern;
+
+// eyes with no subcommand is the same as `eyes run`
+#[derive(Debug, Parser)]
+#[clap(
+    name = "eyes",
+    about = "Eyes on LED panels that follow the people in front of them"
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the installation
+    Run {
+        /// TOML config, the built in defaults without it
+        #[clap(long)]
+        config: Option<String>,
+    },
+    /// List serial ports with their USB vendor, product and serial numbers
+    ListSerial,
+    /// List cameras with the index webcams_index takes
+    ListCameras,
+    /// Show a test pattern for checking wiring, every panel or just one
+    TestPattern {
+        /// index of the panel in the config's scraens, the others stay dark
+        #[clap(long)]
+        panel: Option<usize>,
+        #[clap(long, value_enum, default_value = "white")]
+        pattern: TestPattern,
+        #[clap(long)]
+        config: Option<String>,
+    },
+    /// Open with the layout editor on, for placing panels over the camera image
+    Calibrate {
+        #[clap(long)]
+        config: Option<String>,
+    },
+    /// Play a recording back through a serial port with its original timing.
+    /// Recordings don't keep which device each panel went out on, so panels
+    /// recorded from Art-Net or sACN are replayed over serial too
+    Replay {
+        recording: String,
+        /// serial port, the built in PORT from constants.rs without it, a
+        /// config file's port isn't read
+        #[clap(long)]
+        port: Option<String>,
+        #[clap(long)]
+        baud: Option<u32>,
+    },
+}
+
+impl Cli {
+    pub fn command(&self) -> Command {
+        self.command
+            .clone()
+            .unwrap_or(Command::Run { config: None })
+    }
+}
+
+impl Command {
+    pub fn config(&self) -> Option<&str> {
+        match self {
+            Command::Run { config }
+            | Command::TestPattern { config, .. }
+            | Command::Calibrate { config } => config.as_deref(),
+            _ => None,
+        }
+    }
+}
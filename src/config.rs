@@ -0,0 +1,456 @@
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::connection::protocol::MAX_PAYLOAD;
+use crate::constants::*;
+use crate::network;
+use crate::output::Device;
+use crate::scraen::blink::BlinkerDim;
+use crate::scraen::pupil::PupilDim;
+use crate::scraen::tone::ToneDim;
+use crate::timer::Timer;
+use crate::{BlinkDim, ColorFormat, DetectorDim, PixelMapping, ScraenDim, Settings};
+
+// Everything that changes from one site to the next, read from a TOML file
+// given with `--config <path>`. Anything the file leaves out keeps the value
+// from constants.rs, and running without a file uses constants.rs as is.
+// The file is watched while running, see ConfigWatcher.
+//
+//   scale = 2.0
+//   camera_wh = [320, 240]
+//
+//   [[scraens]]
+//   rez = 16
+//   xy = [102.0, -212.0]
+//   wh = [16.0, 16.0]
+//   output = { address = 1, device = { type = "serial", port = "/dev/ttyACM0", settings = { baud = 115200 }, encoding = "rle" } }
+//   mapping = { lines = { serpentine = "even_reversed", gap = 1 } }
+//   tone = { gamma = 2.2, brightness = 0.6 }
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub scraens: Vec<ScraenDim>,
+    pub settings: Option<Settings>,
+
+    pub scale: f32,
+    // window size before scaling
+    pub window_wh: (f32, f32),
+    pub camera_wh: (u32, u32),
+    pub webcams_index: [usize; 2],
+    pub detector: DetectorDim,
+    pub show_debug: bool,
+    pub blinks: BlinkerDim,
+
+    pub osc_port: u16,
+    pub output_hz: f64,
+    pub output_override: Option<Device>,
+    pub record_to: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            scraens: Vec::from(SCRAENS),
+            settings: None,
+            scale: SCALE,
+            window_wh: (WIDTH, HEIGHT),
+            camera_wh: CAMERA_WH,
+            webcams_index: WEBCAMS_INDEX,
+            detector: DETECTOR,
+            show_debug: SHOWDEBUG,
+            blinks: BLINKER,
+            osc_port: OSC_PORT,
+            output_hz: OUTPUT_HZ,
+            output_override: OUTPUT_OVERRIDE,
+            record_to: RECORD_TO.map(str::to_owned),
+        }
+    }
+}
+
+impl Default for ToneDim {
+    fn default() -> ToneDim {
+        DEFAULT_TONE
+    }
+}
+
+impl Default for BlinkDim {
+    fn default() -> BlinkDim {
+        DEFAULT_BLINK
+    }
+}
+
+impl Default for BlinkerDim {
+    fn default() -> BlinkerDim {
+        BLINKER
+    }
+}
+
+impl Default for PupilDim {
+    fn default() -> PupilDim {
+        DEFAULT_PUPIL
+    }
+}
+
+impl Default for DetectorDim {
+    fn default() -> DetectorDim {
+        DETECTOR
+    }
+}
+
+pub fn default_mapping() -> PixelMapping {
+    SERPENTINE_ROWS
+}
+
+pub fn default_format() -> ColorFormat {
+    ColorFormat::Mono
+}
+
+// how often the config file's modification time is checked
+const WATCH_INTERVAL_SEC: f32 = 0.5;
+
+// Notices when the config file is saved and hands back the new config. A file
+// that doesn't parse or validate is reported and skipped, the running config
+// stays in place until the next save.
+pub struct ConfigWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+    timer: Timer,
+}
+
+impl Config {
+    // the defaults without a path
+    pub fn load_or_default(path: Option<&str>) -> Result<Config> {
+        match path {
+            Some(path) => Config::load(path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Config> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading config {}", path))?;
+        let config: Config = toml::from_str(&text).with_context(|| format!("parsing {}", path))?;
+        config.validate().with_context(|| format!("checking {}", path))?;
+        println!("loaded config {}", path);
+        Ok(config)
+    }
+
+    // writes every setting out, including the ones still at their defaults
+    pub fn save(&self, path: &str) -> Result<()> {
+        self.validate()?;
+        // going through a Value puts plain keys ahead of tables, which toml needs
+        let mut value = toml::Value::try_from(self)?;
+        round_floats(&mut value);
+        std::fs::write(path, toml::to_string_pretty(&value)?)
+            .with_context(|| format!("writing config {}", path))?;
+        println!("saved config {}", path);
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.scraens.is_empty() {
+            bail!("no scraens configured");
+        }
+        if !(self.scale.is_finite() && self.scale > 0.0) {
+            bail!("scale has to be positive, got {}", self.scale);
+        }
+        if !(self.window_wh.0 > 0.0 && self.window_wh.1 > 0.0) {
+            bail!("window_wh has to be positive, got {:?}", self.window_wh);
+        }
+        if self.camera_wh.0 == 0 || self.camera_wh.1 == 0 {
+            bail!("camera_wh can't be zero, got {:?}", self.camera_wh);
+        }
+        if !(self.output_hz > 0.0 && self.output_hz <= 1000.0) {
+            bail!("output_hz has to be between 0 and 1000, got {}", self.output_hz);
+        }
+
+        // written so NaN fails every check
+        let blinks = &self.blinks;
+        if !(blinks.interval_sec.is_finite()
+            && blinks.min_interval_sec >= 0.0
+            && blinks.min_interval_sec <= blinks.interval_sec
+            && blinks.interval_sec > 0.0)
+        {
+            bail!("blinks.interval_sec has to be positive and at least min_interval_sec, which can't be negative");
+        }
+        if !(blinks.stagger_sec.is_finite() && blinks.stagger_sec >= 0.0) {
+            bail!("blinks.stagger_sec can't be negative, got {}", blinks.stagger_sec);
+        }
+        let chances = [blinks.double_chance, blinks.half_chance, blinks.wink_chance];
+        if chances.iter().any(|c| !(0.0..=1.0).contains(c))
+            || blinks.double_chance + blinks.half_chance > 1.0
+        {
+            bail!("blinks chances have to be between 0 and 1, and double and half add up to 1 at most");
+        }
+        if !(0.0..=1.0).contains(&blinks.half_depth) {
+            bail!("blinks.half_depth has to be between 0 and 1, got {}", blinks.half_depth);
+        }
+
+        // rustface panics on anything outside these
+        let detector = &self.detector;
+        if detector.min_face_size < 20 {
+            bail!("detector.min_face_size has to be at least 20, got {}", detector.min_face_size);
+        }
+        if !(detector.score_thresh.is_finite() && detector.score_thresh > 0.0) {
+            bail!("detector.score_thresh has to be positive, got {}", detector.score_thresh);
+        }
+        if !(0.01..=0.99).contains(&detector.pyramid_scale_factor) {
+            bail!(
+                "detector.pyramid_scale_factor has to be between 0.01 and 0.99, got {}",
+                detector.pyramid_scale_factor
+            );
+        }
+        if detector.slide_window_step.0 == 0 || detector.slide_window_step.1 == 0 {
+            bail!("detector.slide_window_step can't be zero, got {:?}", detector.slide_window_step);
+        }
+
+        let mut used: Vec<(&Device, u16)> = Vec::new();
+        for (i, scraen) in self.scraens.iter().enumerate() {
+            scraen
+                .validate()
+                .with_context(|| format!("scraens[{}]", i))?;
+
+            let device = self.output_override.as_ref().unwrap_or(&scraen.output.device);
+            check_address(device, scraen).with_context(|| format!("scraens[{}]", i))?;
+            check_payload(device, scraen).with_context(|| format!("scraens[{}]", i))?;
+            if used.contains(&(device, scraen.output.address)) {
+                bail!(
+                    "scraens[{}]: address {} is already used on {:?}",
+                    i,
+                    scraen.output.address,
+                    device
+                );
+            }
+            used.push((device, scraen.output.address));
+        }
+        Ok(())
+    }
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &str, time: f32) -> ConfigWatcher {
+        ConfigWatcher {
+            path: path.to_owned(),
+            modified: modified(path),
+            timer: Timer::start_new(time, WATCH_INTERVAL_SEC),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn poll(&mut self, time: f32) -> Option<Config> {
+        if !self.timer.check(time) {
+            return None;
+        }
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        match Config::load(&self.path) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                println!("keeping the running config: {:#}", err);
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// f32 settings come out of serde widened to f64, 0.6 as 0.6000000238418579.
+// printing them as f32 first gives back what was typed. only floats that are
+// exactly an f32 are touched, the f64 settings keep their precision
+fn round_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(f) => {
+            let narrow = *f as f32;
+            if narrow as f64 == *f {
+                if let Ok(rounded) = narrow.to_string().parse() {
+                    *f = rounded;
+                }
+            }
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(round_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| round_floats(v)),
+        _ => {}
+    }
+}
+
+// the serial protocol has a byte for the panel, the network protocols take
+// a universe per 512 channels starting from the address
+fn check_address(device: &Device, scraen: &ScraenDim) -> Result<()> {
+    let address = scraen.output.address;
+    let (first, last) = match device {
+        Device::Serial { .. } | Device::File { .. } => {
+            if address > u8::MAX as u16 {
+                bail!("address {} doesn't fit in a byte, the most is 255", address);
+            }
+            return Ok(());
+        }
+        Device::ArtNet { .. } => (0, network::ARTNET_MAX_UNIVERSE),
+        Device::Sacn { .. } => (network::SACN_MIN_UNIVERSE, network::SACN_MAX_UNIVERSE),
+        Device::Null => return Ok(()),
+    };
+
+    let bytes_per_pixel = scraen.format.bytes_per_pixel();
+    let table = scraen.mapping.table((scraen.rez, scraen.rez))?;
+    let count = network::universe_count(table.len() * bytes_per_pixel, bytes_per_pixel);
+    let end = u16::try_from(count - 1)
+        .ok()
+        .and_then(|n| address.checked_add(n));
+    match end {
+        Some(end) if address >= first && end <= last => Ok(()),
+        _ => bail!(
+            "address {} needs {} universes, they have to be between {} and {}",
+            address,
+            count,
+            first,
+            last
+        ),
+    }
+}
+
+// a file gets the same packets a serial controller would
+fn check_payload(device: &Device, scraen: &ScraenDim) -> Result<()> {
+    if !matches!(device, Device::Serial { .. } | Device::File { .. }) {
+        return Ok(());
+    }
+    let table = scraen.mapping.table((scraen.rez, scraen.rez))?;
+    let len = table.len() * scraen.format.bytes_per_pixel();
+    if len > MAX_PAYLOAD {
+        bail!(
+            "{} bytes per frame doesn't fit a serial packet, the most is {}",
+            len,
+            MAX_PAYLOAD
+        );
+    }
+    Ok(())
+}
+
+impl ScraenDim {
+    pub fn validate(&self) -> Result<()> {
+        if self.rez == 0 || self.rez > 255 {
+            bail!("rez has to be between 1 and 255, got {}", self.rez);
+        }
+        if !(self.xy.0.is_finite() && self.xy.1.is_finite()) {
+            bail!("xy has to be a number, got {:?}", self.xy);
+        }
+        let (w, h) = self.wh;
+        if !(w.is_finite() && h.is_finite() && w > 0.0 && h > 0.0) {
+            bail!("wh has to be positive, got {:?}", self.wh);
+        }
+
+        let tone = &self.tone;
+        if tone.gamma <= 0.0 {
+            bail!("tone.gamma has to be positive, got {}", tone.gamma);
+        }
+        if !(0.0..=1.0).contains(&tone.brightness) {
+            bail!("tone.brightness has to be between 0 and 1, got {}", tone.brightness);
+        }
+        if tone.current_budget_ma.is_some_and(|ma| ma <= 0.0) {
+            bail!("tone.current_budget_ma has to be positive");
+        }
+
+        let blink = &self.blink;
+        let times = [blink.shutting_time, blink.closed_time, blink.opening_time];
+        if !times.iter().all(|t| t.is_finite() && *t >= 0.0) {
+            bail!("blink times can't be negative, got {:?}", times);
+        }
+
+        let pupil = &self.pupil;
+        if !pupil.light.is_valid() || !pupil.face.is_valid() {
+            bail!("pupil curves need their inputs in increasing order");
+        }
+        if pupil.constrict_sec < 0.0 || pupil.dilate_sec < 0.0 {
+            bail!("pupil ease times can't be negative");
+        }
+
+        self.mapping
+            .table((self.rez, self.rez))
+            .context("mapping")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> Result<Config> {
+        let path = std::env::temp_dir().join(format!("eyes-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let config = Config::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn save_rounds_only_f32_settings() {
+        let config = Config {
+            scale: 0.6,
+            blinks: BlinkerDim {
+                interval_sec: 4.123456789012,
+                ..BLINKER
+            },
+            ..Config::default()
+        };
+        let path = std::env::temp_dir().join(format!("eyes-{}-save.toml", std::process::id()));
+        config.save(path.to_str().unwrap()).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(text.contains("scale = 0.6\n"), "{}", text);
+        assert!(text.contains("interval_sec = 4.123456789012\n"), "{}", text);
+        assert_eq!(toml::from_str::<Config>(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn blinks_reject_negative_and_nan() {
+        for blinks in [
+            "min_interval_sec = -1.0",
+            "stagger_sec = -0.1",
+            "interval_sec = nan",
+            "half_depth = nan",
+        ] {
+            let err = load("blinks", &format!("[blinks]\n{}\n", blinks)).unwrap_err();
+            assert!(format!("{:#}", err).contains("blinks"), "{}", blinks);
+        }
+    }
+
+    #[test]
+    fn detector_min_face_size_too_small() {
+        let err = load("detector", "[detector]\nmin_face_size = 10\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("detector.min_face_size"));
+    }
+
+    // 40x40 rgb is 4800 bytes, fine over Art-Net but too big for a packet
+    const BIG_ARTNET_PANEL: &str = r#"
+[[scraens]]
+rez = 40
+xy = [0.0, 0.0]
+wh = [40.0, 40.0]
+format = "rgb888"
+output = { address = 1, device = { type = "artnet", target = "127.0.0.1" } }
+"#;
+
+    #[test]
+    fn payload_checked_on_the_override() {
+        assert!(load("artnet", BIG_ARTNET_PANEL).is_ok());
+
+        let text = format!(
+            "output_override = {{ type = \"file\", path = \"panels.bin\" }}\n{}",
+            BIG_ARTNET_PANEL
+        );
+        let err = load("override", &text).unwrap_err();
+        assert!(format!("{:#}", err).contains("doesn't fit a serial packet"));
+    }
+}
@@ -1,57 +1,847 @@
 use nannou::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 // #[derive(Debug, PartialEq)]
 
+pub use eyes::connection::protocol;
+use protocol::{Decoder, Kind, Packet};
+pub mod serial;
+use serial::{discover_ports, Port, SerialSettings};
+
+// how many sent panel packets are kept around to answer a NAK
+const RESEND_HISTORY: usize = 32;
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+const BACKOFF_START: Duration = Duration::from_millis(250);
+const BACKOFF_MAX: Duration = Duration::from_secs(8);
+// a full frame goes out at least this often when sending deltas
+const KEYFRAME_INTERVAL: u32 = 60;
+
 pub struct Connection {
-    port_name: String,
-    port: Option<SerialPort>,
-    connected: bool,
+    port: Port,
+    settings: SerialSettings,
+    state: LinkState,
     print_activity: bool,
+    seq: u16,
+
+    reader: Option<Reader>,
+    sent: VecDeque<(u16, Vec<u8>)>,
+    health: HashMap<u8, PanelHealth>,
+
+    encoding: Encoding,
+    codecs: HashMap<u8, PanelCodec>,
+}
+
+// how panel pixels are packed on the link, see protocol.rs
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Raw,
+    // run length, falls back to raw when that is smaller
+    Rle,
+    // only what changed since the last acknowledged frame, with periodic keyframes.
+    // needs a controller that sends ACKs, without them every frame is a keyframe
+    Delta,
+}
+
+#[derive(Default)]
+struct PanelCodec {
+    // last frame the controller acknowledged, the base for deltas
+    acked: Option<(u16, Vec<u8>)>,
+    in_flight: VecDeque<(u16, Vec<u8>)>,
+    since_keyframe: u32,
+    force_keyframe: bool,
+}
+
+// Disconnected -> Connecting -> Connected, and on a failed open or a write/read
+// error -> Backoff, which goes back to Connecting once the delay has passed.
+// opening happens on a short lived thread so a slow device never stalls update
+pub enum LinkState {
+    Disconnected,
+    Connecting {
+        attempt: u32,
+        result: Receiver<io::Result<SerialPort>>,
+    },
+    Connected(Arc<SerialPort>),
+    Backoff {
+        attempt: u32,
+        until: Instant,
+    },
 }
 use serial2::SerialPort;
 
+use crate::output::{OutputDim, OutputSink, Outputs, PanelFrame};
+
+// every panel of one composed frame, with where it goes
+pub type ComposedFrame = Vec<(OutputDim, PanelFrame)>;
+
+// Sends the latest composed frame to the outputs at a fixed rate, so the
+// panels refresh steadily whatever the window and face detection are doing.
+// update hands frames over through a LatestSlot and never waits on the thread
+pub struct OutputThread {
+    slot: Arc<LatestSlot<ComposedFrame>>,
+    stats: Arc<OutputStats>,
+    health: Arc<Mutex<Vec<(OutputDim, PanelHealth)>>>,
+    refresh_hz: f64,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct OutputStats {
+    sent: AtomicU64,
+    // replaced in the slot before the thread got to them
+    dropped: AtomicU64,
+    // ticks with no new frame, the previous one went out again
+    duplicated: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub refresh_hz: f64,
+    pub sent: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+}
+
+// single value mailbox, the writer replaces whatever the reader hasn't taken yet
+pub struct LatestSlot<T> {
+    ptr: AtomicPtr<T>,
+    _owns: PhantomData<Box<T>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Ack { panel: u8, seq: u16 },
+    Nak { panel: u8, seq: u16 },
+    Telemetry { panel: u8, telemetry: Telemetry },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Telemetry {
+    pub refresh_hz: f32,
+    pub supply_volts: f32,
+    pub temperature_c: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PanelHealth {
+    pub acks: u32,
+    pub naks: u32,
+    pub telemetry: Option<Telemetry>,
+    pub last_heard: Instant,
+}
+
+struct Reader {
+    events: Receiver<Event>,
+    stop: Arc<AtomicBool>,
+    // set by the reader thread when the port stops answering reads
+    lost: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
 impl Connection {
-    pub fn new(port_name: &str, print_activity: bool) -> Connection {
+    pub fn new(port: Port, settings: SerialSettings, print_activity: bool) -> Connection {
         Connection {
-            port: None,
-            connected: false,
+            encoding: Encoding::Raw,
+            codecs: HashMap::new(),
+            settings,
+            state: LinkState::Disconnected,
             print_activity,
-            port_name: port_name.to_owned(),
+            port,
+            seq: 0,
+            reader: None,
+            sent: VecDeque::with_capacity(RESEND_HISTORY),
+            health: HashMap::new(),
         }
     }
 
     pub fn print_avaliable_ports() {
-        for path in SerialPort::available_ports().unwrap() {
-            println!("{:?}", path);
+        match discover_ports() {
+            Ok(ports) if ports.is_empty() => println!("no serial ports found"),
+            Ok(ports) => {
+                for info in ports {
+                    println!("{}", info);
+                }
+            }
+            Err(err) => println!("couldn't list serial ports: {}", err),
         }
     }
 
-    pub fn write(&mut self, mut vec: Vec<u8>) {
-        self.open_port();
-
-        if let Some(port) = &self.port {
-            port.write(&vec);
-        }
+    pub fn with_encoding(mut self, encoding: Encoding) -> Connection {
+        self.encoding = encoding;
+        self
     }
 
-    pub fn open_port(&mut self) {
-        match SerialPort::open(&self.port_name, 115200) {
-            Ok(p) => {
-                if !self.connected {
-                    self.port = Some(p);
+    // drives the link state machine, call once per update. never blocks
+    pub fn update_link(&mut self) {
+        let lost = match &self.reader {
+            Some(reader) => reader.lost.load(Ordering::Relaxed),
+            None => false,
+        };
+        if lost {
+            self.disconnect("port stopped answering");
+        }
 
-                    self.connected = true;
+        let next = match &self.state {
+            LinkState::Disconnected => Some(self.start_connecting(0)),
+            LinkState::Connecting { attempt, result } => match result.try_recv() {
+                Ok(Ok(port)) => {
                     if self.print_activity {
-                        println!("port connected");
+                        println!("port connected: {}", self.port);
+                    }
+                    let port = Arc::new(port);
+                    self.start_reader(port.clone());
+                    Some(LinkState::Connected(port))
+                }
+                Ok(Err(err)) => {
+                    if self.print_activity && *attempt == 0 {
+                        println!("couldn't open port {}: {}", self.port, err);
                     }
+                    Some(backoff(*attempt))
+                }
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => Some(backoff(*attempt)),
+            },
+            LinkState::Connected(_) => None,
+            LinkState::Backoff { attempt, until } => {
+                if Instant::now() >= *until {
+                    Some(self.start_connecting(*attempt))
+                } else {
+                    None
                 }
             }
-            Err(_Err) => {
-                self.connected = false;
+        };
+
+        if let Some(state) = next {
+            self.state = state;
+        }
+    }
+
+    pub fn state(&self) -> &LinkState {
+        &self.state
+    }
 
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, LinkState::Connected(_))
+    }
+
+    // writes are dropped while the link is down, the next frame replaces them anyway
+    pub fn write(&mut self, vec: Vec<u8>) {
+        let result = match &self.state {
+            LinkState::Connected(port) => write_all(port, &vec),
+            _ => return,
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
                 if self.print_activity {
-                    println!("couldn't open port: {}", { _Err });
+                    println!("write to {} timed out", self.port);
+                }
+            }
+            Err(err) => self.disconnect(&err.to_string()),
+        }
+    }
+
+    fn disconnect(&mut self, reason: &str) {
+        self.stop_reader();
+        if let LinkState::Connected(_) = self.state {
+            if self.print_activity {
+                println!("port {} lost: {}", self.port, reason);
+            }
+            self.state = backoff(0);
+        }
+    }
+
+    fn start_connecting(&self, attempt: u32) -> LinkState {
+        let (tx, rx) = channel();
+        let (port, settings) = (self.port.clone(), self.settings);
+        thread::spawn(move || {
+            // usb selectors are resolved here, the device may have been renumbered
+            let result = port
+                .resolve()
+                .and_then(|path| settings.open(&path))
+                .and_then(|mut port| {
+                    // set before the port is shared with the reader, serial2
+                    // needs it mutable
+                    port.set_read_timeout(READ_TIMEOUT)?;
+                    port.set_write_timeout(WRITE_TIMEOUT)?;
+                    Ok(port)
+                });
+            let _ = tx.send(result);
+        });
+
+        LinkState::Connecting {
+            attempt,
+            result: rx,
+        }
+    }
+
+    // sends a panel packet again after the controller NAKed it. packets that
+    // already fell out of the history are skipped, the next frame replaces them anyway
+    pub fn resend(&mut self, seq: u16) {
+        let bytes = self
+            .sent
+            .iter()
+            .find(|(s, _)| *s == seq)
+            .map(|(_, bytes)| bytes.clone());
+        if let Some(bytes) = bytes {
+            self.write(bytes);
+        }
+    }
+
+    // tells every panel on this port to show what it was sent since the last present
+    pub fn present(&mut self) {
+        let packet = Packet::present(self.next_seq());
+        self.write(packet.encode());
+    }
+
+    fn next_seq(&mut self) -> u16 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+
+    // drains everything the reader thread has heard since the last call
+    pub fn poll_events(&mut self) -> Vec<Event> {
+        let events: Vec<Event> = match &self.reader {
+            Some(reader) => reader.events.try_iter().collect(),
+            None => Vec::new(),
+        };
+
+        for event in &events {
+            let panel = match event {
+                Event::Ack { panel, .. } => panel,
+                Event::Nak { panel, .. } => panel,
+                Event::Telemetry { panel, .. } => panel,
+            };
+            let health = self.health.entry(*panel).or_insert(PanelHealth {
+                acks: 0,
+                naks: 0,
+                telemetry: None,
+                last_heard: Instant::now(),
+            });
+            health.last_heard = Instant::now();
+            match event {
+                Event::Ack { .. } => health.acks += 1,
+                Event::Nak { .. } => health.naks += 1,
+                Event::Telemetry { telemetry, .. } => health.telemetry = Some(*telemetry),
+            }
+        }
+        events
+    }
+
+    fn start_reader(&mut self, port: Arc<SerialPort>) {
+        self.stop_reader();
+
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let lost = Arc::new(AtomicBool::new(false));
+        let (thread_stop, thread_lost) = (stop.clone(), lost.clone());
+        let print_activity = self.print_activity;
+        let handle = thread::spawn(move || {
+            read_loop(port, tx, thread_stop, print_activity);
+            thread_lost.store(true, Ordering::Relaxed);
+        });
+
+        self.reader = Some(Reader {
+            events: rx,
+            stop,
+            lost,
+            handle,
+        });
+    }
+
+    fn stop_reader(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            reader.stop.store(true, Ordering::Relaxed);
+            let _ = reader.handle.join();
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stop_reader();
+    }
+}
+
+impl OutputSink for Connection {
+    // steps the link and resends whatever the controller NAKed
+    fn update(&mut self) {
+        self.update_link();
+        for event in self.poll_events() {
+            match event {
+                Event::Ack { panel, seq } => {
+                    if let Some(codec) = self.codecs.get_mut(&panel) {
+                        codec.acked(seq);
+                    }
+                }
+                // a resent delta could be stale by now, start over from a keyframe
+                Event::Nak { panel, .. } if self.encoding == Encoding::Delta => {
+                    self.codecs.entry(panel).or_default().force_keyframe = true;
+                }
+                Event::Nak { seq, .. } => self.resend(seq),
+                Event::Telemetry { .. } => {}
+            }
+        }
+    }
+
+    fn send_panel(&mut self, panel: &PanelFrame) {
+        // Config::validate keeps serial addresses to a byte
+        let address = match u8::try_from(panel.address) {
+            Ok(address) => address,
+            Err(_) => {
+                println!("panel address {} is too big for serial", panel.address);
+                return;
+            }
+        };
+        let seq = self.next_seq();
+        let (kind, payload) = self.encode_panel(address, seq, panel);
+
+        let mut packet = Packet::panel(address, panel.wh, panel.format.to_byte(), seq, payload);
+        packet.header.kind = kind;
+        let bytes = packet.encode();
+
+        if self.sent.len() == RESEND_HISTORY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((seq, bytes.clone()));
+        self.write(bytes);
+    }
+
+    fn end_frame(&mut self) {
+        self.present();
+    }
+
+    fn health(&self, address: u16) -> Option<&PanelHealth> {
+        self.health.get(&u8::try_from(address).ok()?)
+    }
+}
+
+impl Connection {
+    fn encode_panel(&mut self, address: u8, seq: u16, panel: &PanelFrame) -> (Kind, Vec<u8>) {
+        if self.encoding == Encoding::Raw {
+            return (Kind::Panel, panel.data.clone());
+        }
+        self.codecs
+            .entry(address)
+            .or_default()
+            .encode(self.encoding, seq, panel)
+    }
+}
+
+impl PanelCodec {
+    // a keyframe is rle or raw, whichever is smaller
+    fn encode(&mut self, encoding: Encoding, seq: u16, panel: &PanelFrame) -> (Kind, Vec<u8>) {
+        let bpp = panel.format.bytes_per_pixel();
+        let rle = protocol::rle_encode(&panel.data, bpp);
+        let keyframe = if rle.len() < panel.data.len() {
+            (Kind::PanelRle, rle)
+        } else {
+            (Kind::Panel, panel.data.clone())
+        };
+
+        if self.in_flight.len() == RESEND_HISTORY {
+            self.in_flight.pop_front();
+        }
+        self.in_flight.push_back((seq, panel.data.clone()));
+
+        if encoding == Encoding::Delta
+            && !self.force_keyframe
+            && self.since_keyframe < KEYFRAME_INTERVAL
+        {
+            if let Some((base_seq, base)) = &self.acked {
+                if base.len() == panel.data.len() {
+                    let delta = protocol::delta_encode(*base_seq, base, &panel.data, bpp);
+                    if delta.len() < keyframe.1.len() {
+                        self.since_keyframe += 1;
+                        return (Kind::PanelDelta, delta);
+                    }
+                }
+            }
+        }
+
+        self.since_keyframe = 0;
+        self.force_keyframe = false;
+        keyframe
+    }
+
+    fn acked(&mut self, seq: u16) {
+        if let Some(i) = self.in_flight.iter().position(|(s, _)| *s == seq) {
+            self.acked = self.in_flight.drain(..=i).next_back();
+        }
+    }
+}
+
+impl OutputThread {
+    pub fn spawn(mut outputs: Outputs, refresh_hz: f64) -> OutputThread {
+        let slot = Arc::new(LatestSlot::new());
+        let stats = Arc::new(OutputStats::default());
+        let health = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (thread_slot, thread_stats, thread_health, thread_stop) =
+            (slot.clone(), stats.clone(), health.clone(), stop.clone());
+        let handle = thread::spawn(move || {
+            let period = Duration::from_secs_f64(1.0 / refresh_hz);
+            let mut next_tick = Instant::now();
+            let mut last: Option<ComposedFrame> = None;
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                outputs.update();
+
+                match thread_slot.take() {
+                    Some(frame) => last = Some(frame),
+                    None if last.is_some() => {
+                        thread_stats.duplicated.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => {}
+                }
+                if let Some(frame) = &last {
+                    outputs.begin_frame();
+                    for (output, panel) in frame {
+                        outputs.send_panel(output, panel);
+                    }
+                    outputs.end_frame();
+                    thread_stats.sent.fetch_add(1, Ordering::Relaxed);
+
+                    if let Ok(mut health) = thread_health.lock() {
+                        *health = frame
+                            .iter()
+                            .filter_map(|(output, _)| {
+                                Some((output.clone(), *outputs.health(output)?))
+                            })
+                            .collect();
+                    }
+                }
+
+                // fall back into step rather than bursting after a stall
+                next_tick += period;
+                let now = Instant::now();
+                if next_tick > now {
+                    thread::sleep(next_tick - now);
+                } else {
+                    next_tick = now;
+                }
+            }
+        });
+
+        OutputThread {
+            slot,
+            stats,
+            health,
+            refresh_hz,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn submit(&self, frame: ComposedFrame) {
+        if self.slot.put(frame) {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            refresh_hz: self.refresh_hz,
+            sent: self.stats.sent.load(Ordering::Relaxed),
+            dropped: self.stats.dropped.load(Ordering::Relaxed),
+            duplicated: self.stats.duplicated.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn health(&self, output: &OutputDim) -> Option<PanelHealth> {
+        let health = self.health.try_lock().ok()?;
+        health
+            .iter()
+            .find(|(o, _)| o == output)
+            .map(|(_, health)| *health)
+    }
+
+    // stops sending and closes every output, frames submitted after are dropped
+    pub fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for OutputThread {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// SAFETY: the slot owns the value it holds and take hands it to whichever
+// thread calls it, so sharing the slot means sending T. The swap gives every
+// pointer a single owner, nothing is ever reached through &T.
+unsafe impl<T: Send> Send for LatestSlot<T> {}
+unsafe impl<T: Send> Sync for LatestSlot<T> {}
+
+impl<T> Default for LatestSlot<T> {
+    fn default() -> LatestSlot<T> {
+        LatestSlot::new()
+    }
+}
+
+impl<T> LatestSlot<T> {
+    pub fn new() -> LatestSlot<T> {
+        LatestSlot {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            _owns: PhantomData,
+        }
+    }
+
+    // returns true if a value nobody took was thrown away
+    pub fn put(&self, value: T) -> bool {
+        let new = Box::into_raw(Box::new(value));
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+        if old.is_null() {
+            false
+        } else {
+            // swap handed us sole ownership of old
+            drop(unsafe { Box::from_raw(old) });
+            true
+        }
+    }
+
+    pub fn take(&self) -> Option<T> {
+        let old = self.ptr.swap(ptr::null_mut(), Ordering::AcqRel);
+        if old.is_null() {
+            None
+        } else {
+            Some(*unsafe { Box::from_raw(old) })
+        }
+    }
+}
+
+impl<T> Drop for LatestSlot<T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+fn backoff(attempt: u32) -> LinkState {
+    let delay = BACKOFF_START
+        .checked_mul(1 << attempt.min(16))
+        .unwrap_or(BACKOFF_MAX)
+        .min(BACKOFF_MAX);
+    LinkState::Backoff {
+        attempt: attempt + 1,
+        until: Instant::now() + delay,
+    }
+}
+
+// serial2 only implements Write for an owned port, the link shares it with
+// the reader
+fn write_all(port: &SerialPort, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match port.write(bytes) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => bytes = &bytes[n..],
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn read_loop(port: Arc<SerialPort>, events: Sender<Event>, stop: Arc<AtomicBool>, print: bool) {
+    let mut decoder = Decoder::new();
+    let mut buf = [0u8; 256];
+    while !stop.load(Ordering::Relaxed) {
+        match port.read(&mut buf) {
+            Ok(0) => {}
+            Ok(n) => decoder.push(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                if print {
+                    println!("couldn't read from port: {}", err);
                 }
+                return;
             }
         }
+
+        while let Some(result) = decoder.next_packet() {
+            match result {
+                Ok(packet) => {
+                    if let Some(event) = Event::from_packet(&packet) {
+                        if events.send(event).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(err) => {
+                    if print {
+                        println!("bad packet from controller: {}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Event {
+    pub fn from_packet(packet: &Packet) -> Option<Event> {
+        let panel = packet.header.panel;
+        let seq = packet.header.seq;
+        match packet.header.kind {
+            Kind::Ack => Some(Event::Ack { panel, seq }),
+            Kind::Nak => Some(Event::Nak { panel, seq }),
+            Kind::Telemetry => {
+                let p = &packet.payload;
+                if p.len() < 6 {
+                    return None;
+                }
+                Some(Event::Telemetry {
+                    panel,
+                    telemetry: Telemetry {
+                        refresh_hz: u16::from_le_bytes([p[0], p[1]]) as f32 / 100.0,
+                        supply_volts: u16::from_le_bytes([p[2], p[3]]) as f32 / 1000.0,
+                        temperature_c: i16::from_le_bytes([p[4], p[5]]) as f32 / 10.0,
+                    },
+                })
+            }
+            Kind::Panel | Kind::PanelRle | Kind::PanelDelta | Kind::Present => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scraen::ColorFormat;
+    use std::sync::atomic::AtomicUsize;
+
+    // counts its drops, to see the slot free what it holds
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn slot_put_then_take() {
+        let slot = LatestSlot::new();
+        assert_eq!(slot.take(), None);
+        assert!(!slot.put(1));
+        assert_eq!(slot.take(), Some(1));
+        assert_eq!(slot.take(), None);
+    }
+
+    #[test]
+    fn slot_put_drops_what_was_not_taken() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let slot = LatestSlot::new();
+        assert!(!slot.put(Counted(drops.clone())));
+        assert!(slot.put(Counted(drops.clone())));
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+
+        let taken = slot.take();
+        assert!(taken.is_some());
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        drop(taken);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn slot_drop_frees_the_pending_value() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let slot = LatestSlot::new();
+        slot.put(Counted(drops.clone()));
+        drop(slot);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn output_thread_counts_dropped_and_duplicated() {
+        // ticks at 0, 50, 100 and 150ms
+        let mut thread = OutputThread::spawn(Outputs::new(std::iter::empty()), 20.0);
+        thread::sleep(Duration::from_millis(10));
+
+        // both land before the 50ms tick, the first is replaced
+        thread.submit(Vec::new());
+        thread.submit(Vec::new());
+        thread::sleep(Duration::from_millis(165));
+        thread.shutdown();
+
+        let stats = thread.stats();
+        assert_eq!(stats.dropped, 1);
+        assert!(stats.sent >= 2, "sent {}", stats.sent);
+        assert_eq!(stats.duplicated, stats.sent - 1);
+    }
+
+    // 64 different values so rle never beats raw
+    fn panel(changed: u8) -> PanelFrame {
+        let mut data: Vec<u8> = (0..64).collect();
+        data[0] = changed;
+        PanelFrame {
+            address: 0,
+            wh: (8, 8),
+            format: ColorFormat::Mono,
+            data,
+        }
+    }
+
+    #[test]
+    fn keyframe_interval_forces_a_full_frame() {
+        let mut codec = PanelCodec::default();
+        let (kind, _) = codec.encode(Encoding::Delta, 0, &panel(0));
+        assert_eq!(kind, Kind::Panel);
+        codec.acked(0);
+
+        for seq in 1..=KEYFRAME_INTERVAL as u16 {
+            let frame = panel(seq as u8 + 100);
+            let (kind, payload) = codec.encode(Encoding::Delta, seq, &frame);
+            assert_eq!(kind, Kind::PanelDelta, "frame {}", seq);
+            let (_, base) = codec.acked.as_ref().unwrap();
+            assert_eq!(protocol::delta_apply(base, &payload, 1), Some(frame.data));
+            codec.acked(seq);
+        }
+
+        let seq = KEYFRAME_INTERVAL as u16 + 1;
+        let (kind, payload) = codec.encode(Encoding::Delta, seq, &panel(0));
+        assert_eq!(kind, Kind::Panel);
+        assert_eq!(payload, panel(0).data);
+        codec.acked(seq);
+
+        // and deltas again after it
+        let (kind, _) = codec.encode(Encoding::Delta, seq + 1, &panel(1));
+        assert_eq!(kind, Kind::PanelDelta);
+    }
+
+    #[test]
+    fn no_acks_means_keyframes() {
+        let mut codec = PanelCodec::default();
+        for seq in 0..3 {
+            let (kind, _) = codec.encode(Encoding::Delta, seq, &panel(seq as u8));
+            assert_eq!(kind, Kind::Panel);
+        }
+    }
+
+    #[test]
+    fn rle_keyframe_when_smaller() {
+        let mut codec = PanelCodec::default();
+        let mut frame = panel(0);
+        frame.data = vec![9; 64];
+        let (kind, payload) = codec.encode(Encoding::Rle, 0, &frame);
+        assert_eq!(kind, Kind::PanelRle);
+        assert_eq!(protocol::rle_decode(&payload, 1), Some(frame.data));
     }
 }
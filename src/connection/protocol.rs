@@ -0,0 +1,627 @@
+// Framing used on the serial link between the host and the panel controllers.
+//
+// Every chunk on the wire is a packet with the layout below. Multi-byte
+// fields are little endian.
+//
+//   offset  size  field
+//   0       2     magic, always 0xE7 0x5E
+//   2       1     protocol version (PROTOCOL_VERSION)
+//   3       1     packet kind (see `Kind`)
+//   4       1     panel id
+//   5       1     panel width in pixels
+//   6       1     panel height in pixels
+//   7       1     pixel format of the payload (ColorFormat wire value, 0 = mono)
+//   8       2     sequence number, wraps at u16::MAX
+//   10      2     payload length n
+//   12      n     payload
+//   12+n    2     CRC-16/CCITT-FALSE of bytes 2..12+n (everything but magic and crc)
+//
+// A host frame is one `Kind::Panel` packet per panel followed by a single
+// `Kind::Present` packet, which tells the controllers to latch what they
+// received. Payload bytes can take any value, so the magic is only a hint
+// for resynchronising; the CRC decides whether a packet is real.
+//
+// Controllers answer with packets in the same framing, using the sequence
+// number of the packet they are replying to:
+//
+//   Ack        empty payload, the panel packet arrived intact
+//   Nak        empty payload, the panel packet was corrupt or missing, please resend
+//   Telemetry  6 byte payload:
+//              0  2  measured refresh rate in centihertz (u16)
+//              2  2  supply voltage in millivolts (u16)
+//              4  2  temperature in tenths of a degree celsius (i16)
+//
+// Panel pixels can also be sent compressed, the payload is then one of:
+//
+//   PanelRle    runs of: u8 count (1..=255), one pixel (format bytes per pixel)
+//   PanelDelta  u16 base sequence number, then spans of:
+//               u16 first slot, u16 slot count, count pixels
+//
+// A delta only lists the slots that changed since the frame with the base
+// sequence number, which is always one the controller has acknowledged.
+// Controllers keep their last few accepted frames per panel; if the base
+// isn't among them they NAK and the host answers with a full keyframe. The
+// host also sends a keyframe every so often without being asked. Slots are
+// positions in wire order, so the spans of a dirty rectangle are its lines.
+//
+// A mono panel packet for panel 2 (4x4, sequence 7) with a 16 byte payload of
+// zeros encodes to `E7 5E 02 01 02 04 04 00 07 00 10 00 <16 x 00> B5 0A`.
+//
+// Version 1 had no format byte and only carried mono pixels.
+
+use std::fmt;
+
+pub const MAGIC: [u8; 2] = [0xE7, 0x5E];
+pub const PROTOCOL_VERSION: u8 = 2;
+
+pub const HEADER_LEN: usize = 12;
+pub const CRC_LEN: usize = 2;
+pub const MAX_PAYLOAD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Panel,
+    PanelRle,
+    PanelDelta,
+    Present,
+    Ack,
+    Nak,
+    Telemetry,
+}
+
+impl Kind {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Kind::Panel => 0x01,
+            Kind::Present => 0x02,
+            Kind::PanelRle => 0x03,
+            Kind::PanelDelta => 0x04,
+            Kind::Ack => 0x81,
+            Kind::Nak => 0x82,
+            Kind::Telemetry => 0x83,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Kind> {
+        match byte {
+            0x01 => Some(Kind::Panel),
+            0x02 => Some(Kind::Present),
+            0x03 => Some(Kind::PanelRle),
+            0x04 => Some(Kind::PanelDelta),
+            0x81 => Some(Kind::Ack),
+            0x82 => Some(Kind::Nak),
+            0x83 => Some(Kind::Telemetry),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub kind: Kind,
+    pub panel: u8,
+    pub width: u8,
+    pub height: u8,
+    pub format: u8,
+    pub seq: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub header: Header,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    // not enough bytes yet, nothing was consumed
+    Incomplete,
+    // the bytes at the front are not the start of a packet
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+    PayloadTooLong(usize),
+    BadCrc { expected: u16, found: u16 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Incomplete => write!(f, "incomplete packet"),
+            DecodeError::BadMagic => write!(f, "missing packet magic"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported protocol version {}", v),
+            DecodeError::UnknownKind(k) => write!(f, "unknown packet kind {:#04x}", k),
+            DecodeError::PayloadTooLong(n) => write!(f, "payload of {} bytes is too long", n),
+            DecodeError::BadCrc { expected, found } => {
+                write!(
+                    f,
+                    "crc mismatch: expected {:#06x}, found {:#06x}",
+                    expected, found
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Packet {
+    pub fn panel(
+        panel: u8,
+        (width, height): (u8, u8),
+        format: u8,
+        seq: u16,
+        payload: Vec<u8>,
+    ) -> Packet {
+        Packet {
+            header: Header {
+                version: PROTOCOL_VERSION,
+                kind: Kind::Panel,
+                panel,
+                width,
+                height,
+                format,
+                seq,
+            },
+            payload,
+        }
+    }
+
+    pub fn present(seq: u16) -> Packet {
+        Packet {
+            header: Header {
+                version: PROTOCOL_VERSION,
+                kind: Kind::Present,
+                panel: 0,
+                width: 0,
+                height: 0,
+                format: 0,
+                seq,
+            },
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn reply(kind: Kind, panel: u8, seq: u16, payload: Vec<u8>) -> Packet {
+        Packet {
+            header: Header {
+                version: PROTOCOL_VERSION,
+                kind,
+                panel,
+                width: 0,
+                height: 0,
+                format: 0,
+                seq,
+            },
+            payload,
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len() + CRC_LEN
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        assert!(self.payload.len() <= MAX_PAYLOAD);
+
+        let h = &self.header;
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        buf.extend_from_slice(&MAGIC);
+        buf.push(h.version);
+        buf.push(h.kind.to_byte());
+        buf.push(h.panel);
+        buf.push(h.width);
+        buf.push(h.height);
+        buf.push(h.format);
+        buf.extend_from_slice(&h.seq.to_le_bytes());
+        buf.extend_from_slice(&(self.payload.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&self.payload);
+
+        let crc = crc16(&buf[MAGIC.len()..]);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    // decodes one packet from the front of `buf`, returning it and the number
+    // of bytes it took up
+    pub fn decode(buf: &[u8]) -> Result<(Packet, usize), DecodeError> {
+        if buf.len() < MAGIC.len() {
+            return Err(DecodeError::Incomplete);
+        }
+        if buf[..MAGIC.len()] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        if buf.len() < HEADER_LEN {
+            return Err(DecodeError::Incomplete);
+        }
+
+        let version = buf[2];
+        if version != PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let kind = Kind::from_byte(buf[3]).ok_or(DecodeError::UnknownKind(buf[3]))?;
+        let len = u16::from_le_bytes([buf[10], buf[11]]) as usize;
+        if len > MAX_PAYLOAD {
+            return Err(DecodeError::PayloadTooLong(len));
+        }
+
+        let total = HEADER_LEN + len + CRC_LEN;
+        if buf.len() < total {
+            return Err(DecodeError::Incomplete);
+        }
+
+        let expected = crc16(&buf[MAGIC.len()..HEADER_LEN + len]);
+        let found = u16::from_le_bytes([buf[total - 2], buf[total - 1]]);
+        if expected != found {
+            return Err(DecodeError::BadCrc { expected, found });
+        }
+
+        let packet = Packet {
+            header: Header {
+                version,
+                kind,
+                panel: buf[4],
+                width: buf[5],
+                height: buf[6],
+                format: buf[7],
+                seq: u16::from_le_bytes([buf[8], buf[9]]),
+            },
+            payload: buf[HEADER_LEN..HEADER_LEN + len].to_vec(),
+        };
+        Ok((packet, total))
+    }
+}
+
+// Streaming decoder for a byte stream that may start mid packet or drop
+// bytes. This is the same state machine the firmware is expected to run.
+#[derive(Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    dropped_bytes: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // bytes thrown away while looking for a valid packet
+    pub fn dropped_bytes(&self) -> usize {
+        self.dropped_bytes
+    }
+
+    // returns the next packet, or None once the buffered bytes run out.
+    // corrupt packets are skipped a byte at a time until the next magic lines up
+    pub fn next_packet(&mut self) -> Option<Result<Packet, DecodeError>> {
+        loop {
+            match Packet::decode(&self.buf) {
+                Ok((packet, used)) => {
+                    self.buf.drain(..used);
+                    return Some(Ok(packet));
+                }
+                Err(DecodeError::Incomplete) => return None,
+                Err(DecodeError::BadMagic) => {
+                    let skip = self.buf[1..]
+                        .iter()
+                        .position(|b| *b == MAGIC[0])
+                        .map(|i| i + 1)
+                        .unwrap_or(self.buf.len());
+                    self.skip(skip);
+                }
+                Err(err) => {
+                    self.skip(1);
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+
+    fn skip(&mut self, n: usize) {
+        self.buf.drain(..n);
+        self.dropped_bytes += n;
+    }
+}
+
+pub fn rle_encode(pixels: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut chunks = pixels.chunks(bytes_per_pixel).peekable();
+    while let Some(pixel) = chunks.next() {
+        let mut count = 1u8;
+        while count < u8::MAX && chunks.peek() == Some(&pixel) {
+            chunks.next();
+            count += 1;
+        }
+        out.push(count);
+        out.extend_from_slice(pixel);
+    }
+    out
+}
+
+pub fn rle_decode(payload: &[u8], bytes_per_pixel: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    for run in payload.chunks(1 + bytes_per_pixel) {
+        if run.len() != 1 + bytes_per_pixel || run[0] == 0 {
+            return None;
+        }
+        for _ in 0..run[0] {
+            out.extend_from_slice(&run[1..]);
+        }
+    }
+    Some(out)
+}
+
+// changed slots between two frames of the same size. unchanged gaps shorter
+// than a span header are folded into the surrounding span
+pub fn delta_encode(base_seq: u16, base: &[u8], pixels: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    const SPAN_HEADER: usize = 4;
+    let bpp = bytes_per_pixel;
+    let slots = pixels.len() / bpp;
+    let changed =
+        |slot: usize| base[slot * bpp..(slot + 1) * bpp] != pixels[slot * bpp..(slot + 1) * bpp];
+
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for slot in (0..slots).filter(|s| changed(*s)) {
+        match spans.last_mut() {
+            Some((start, len)) if (slot - (*start + *len)) * bpp < SPAN_HEADER => {
+                *len = slot - *start + 1
+            }
+            _ => spans.push((slot, 1)),
+        }
+    }
+
+    let mut out = base_seq.to_le_bytes().to_vec();
+    for (start, len) in spans {
+        out.extend_from_slice(&(start as u16).to_le_bytes());
+        out.extend_from_slice(&(len as u16).to_le_bytes());
+        out.extend_from_slice(&pixels[start * bpp..(start + len) * bpp]);
+    }
+    out
+}
+
+pub fn delta_base(payload: &[u8]) -> Option<u16> {
+    Some(u16::from_le_bytes([*payload.first()?, *payload.get(1)?]))
+}
+
+// applies a delta payload to a copy of its base frame
+pub fn delta_apply(base: &[u8], payload: &[u8], bytes_per_pixel: usize) -> Option<Vec<u8>> {
+    let bpp = bytes_per_pixel;
+    let mut out = base.to_vec();
+    let mut rest = payload.get(2..)?;
+    while !rest.is_empty() {
+        let start = u16::from_le_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+        let len = u16::from_le_bytes([*rest.get(2)?, *rest.get(3)?]) as usize;
+        let data = rest.get(4..4 + len * bpp)?;
+        out.get_mut(start * bpp..(start + len) * bpp)?
+            .copy_from_slice(data);
+        rest = &rest[4 + len * bpp..];
+    }
+    Some(out)
+}
+
+// CRC-16/CCITT-FALSE: poly 0x1021, init 0xFFFF, no reflection, no final xor.
+// check value for b"123456789" is 0x29B1
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(packet: Packet) {
+        let bytes = packet.encode();
+        assert_eq!(bytes.len(), packet.encoded_len());
+        assert_eq!(Packet::decode(&bytes), Ok((packet, bytes.len())));
+    }
+
+    #[test]
+    fn every_kind_round_trips() {
+        let pixels: Vec<u8> = (0..48).collect();
+        round_trip(Packet::panel(3, (4, 4), 1, 9, pixels.clone()));
+        for kind in [Kind::PanelRle, Kind::PanelDelta] {
+            let mut packet = Packet::panel(3, (4, 4), 1, 10, pixels.clone());
+            packet.header.kind = kind;
+            round_trip(packet);
+        }
+        round_trip(Packet::present(11));
+        round_trip(Packet::reply(Kind::Ack, 3, 9, Vec::new()));
+        round_trip(Packet::reply(Kind::Nak, 3, 9, Vec::new()));
+        round_trip(Packet::reply(
+            Kind::Telemetry,
+            3,
+            9,
+            vec![0x70, 0x17, 0x88, 0x13, 0xFA, 0x00],
+        ));
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn matches_documented_layout() {
+        let mut expected = vec![
+            0xE7, 0x5E, 0x02, 0x01, 0x02, 0x04, 0x04, 0x00, 0x07, 0x00, 0x10, 0x00,
+        ];
+        expected.extend([0; 16]);
+        expected.extend([0xB5, 0x0A]);
+
+        let bytes = Packet::panel(2, (4, 4), 0, 7, vec![0; 16]).encode();
+        assert_eq!(HEADER_LEN, 12);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_garbage() {
+        let packet = Packet::panel(1, (2, 2), 0, 5, vec![1, 2, 3, 4]);
+        let mut decoder = Decoder::new();
+        // a stray magic byte in the garbage as well
+        decoder.push(&[0x00, 0xE7, 0x13, 0x37]);
+        decoder.push(&packet.encode());
+
+        assert_eq!(decoder.next_packet(), Some(Ok(packet)));
+        assert_eq!(decoder.dropped_bytes(), 4);
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_bad_crc() {
+        let good = Packet::panel(1, (2, 2), 0, 6, vec![1, 2, 3, 4]);
+        let mut bad = Packet::panel(1, (2, 2), 0, 5, vec![9, 9, 9, 9]).encode();
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+
+        let mut decoder = Decoder::new();
+        decoder.push(&bad);
+        decoder.push(&good.encode());
+
+        assert!(matches!(
+            decoder.next_packet(),
+            Some(Err(DecodeError::BadCrc { .. }))
+        ));
+        assert_eq!(decoder.next_packet(), Some(Ok(good)));
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn decoder_waits_for_the_rest() {
+        let packet = Packet::panel(1, (2, 2), 0, 5, vec![1, 2, 3, 4]);
+        let bytes = packet.encode();
+        let mut decoder = Decoder::new();
+        decoder.push(&bytes[..7]);
+        assert_eq!(decoder.next_packet(), None);
+        decoder.push(&bytes[7..]);
+        assert_eq!(decoder.next_packet(), Some(Ok(packet)));
+        assert_eq!(decoder.dropped_bytes(), 0);
+    }
+
+    #[test]
+    fn max_payload_boundary() {
+        round_trip(Packet::panel(0, (64, 64), 0, 0, vec![0xAB; MAX_PAYLOAD]));
+
+        // a header claiming one byte more is rejected before waiting for it
+        let mut bytes = Packet::panel(0, (64, 64), 0, 0, Vec::new()).encode();
+        bytes[10..12].copy_from_slice(&(MAX_PAYLOAD as u16 + 1).to_le_bytes());
+        assert_eq!(
+            Packet::decode(&bytes),
+            Err(DecodeError::PayloadTooLong(MAX_PAYLOAD + 1))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn encode_rejects_oversized_payload() {
+        Packet::panel(0, (64, 64), 0, 0, vec![0; MAX_PAYLOAD + 1]).encode();
+    }
+
+    fn rle_round_trip(pixels: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+        let rle = rle_encode(pixels, bytes_per_pixel);
+        assert_eq!(rle_decode(&rle, bytes_per_pixel).as_deref(), Some(pixels));
+        rle
+    }
+
+    #[test]
+    fn rle_splits_long_runs() {
+        // 255 + 255 + 90 of the same pixel
+        let rle = rle_round_trip(&[1, 2, 3].repeat(600), 3);
+        assert_eq!(
+            rle,
+            [[255, 1, 2, 3], [255, 1, 2, 3], [90, 1, 2, 3]].concat()
+        );
+    }
+
+    #[test]
+    fn rle_empty_and_single_value() {
+        assert_eq!(rle_round_trip(&[], 2), Vec::<u8>::new());
+        assert_eq!(rle_round_trip(&[7; 200], 1), vec![200, 7]);
+        assert_eq!(rle_round_trip(&[0x12, 0x34], 2), vec![1, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn rle_mixed_runs() {
+        let pixels = [1, 1, 1, 2, 3, 3, 1];
+        let rle = rle_round_trip(&pixels, 1);
+        assert_eq!(rle, vec![3, 1, 1, 2, 2, 3, 1, 1]);
+    }
+
+    #[test]
+    fn rle_rejects_bad_payloads() {
+        // a zero count, and a run cut short
+        assert_eq!(rle_decode(&[0, 5], 1), None);
+        assert_eq!(rle_decode(&[2, 5, 6], 3), None);
+    }
+
+    fn delta_round_trip(base: &[u8], pixels: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+        let delta = delta_encode(0x1234, base, pixels, bytes_per_pixel);
+        assert_eq!(delta_base(&delta), Some(0x1234));
+        assert_eq!(
+            delta_apply(base, &delta, bytes_per_pixel).as_deref(),
+            Some(pixels)
+        );
+        delta
+    }
+
+    #[test]
+    fn delta_without_changes() {
+        let base: Vec<u8> = (0..30).collect();
+        let delta = delta_round_trip(&base, &base, 3);
+        assert_eq!(delta, vec![0x34, 0x12]);
+    }
+
+    #[test]
+    fn delta_with_every_pixel_changed() {
+        let base = vec![0; 30];
+        let pixels: Vec<u8> = (1..=30).collect();
+        let delta = delta_round_trip(&base, &pixels, 3);
+        // one span of all 10 slots
+        assert_eq!(delta[..6], [0x34, 0x12, 0, 0, 10, 0]);
+        assert_eq!(delta[6..], pixels[..]);
+    }
+
+    #[test]
+    fn delta_folds_short_gaps() {
+        let base = vec![0; 16];
+
+        // 3 unchanged bytes between slots 0 and 4 are cheaper to resend than
+        // a second span header
+        let mut pixels = base.clone();
+        pixels[0] = 1;
+        pixels[4] = 1;
+        let delta = delta_round_trip(&base, &pixels, 1);
+        assert_eq!(delta, vec![0x34, 0x12, 0, 0, 5, 0, 1, 0, 0, 0, 1]);
+
+        // 4 unchanged bytes start a new span
+        pixels[4] = 0;
+        pixels[5] = 1;
+        let delta = delta_round_trip(&base, &pixels, 1);
+        assert_eq!(delta, vec![0x34, 0x12, 0, 0, 1, 0, 1, 5, 0, 1, 0, 1]);
+
+        // with 2 bytes a pixel the gap is counted in bytes too
+        let delta = delta_round_trip(&base, &pixels, 2);
+        assert_eq!(delta[2..6], [0, 0, 3, 0]);
+    }
+
+    #[test]
+    fn delta_rejects_spans_past_the_frame() {
+        let base = vec![0; 4];
+        assert_eq!(delta_apply(&base, &[0, 0, 3, 0, 2, 0, 1, 1], 1), None);
+        assert_eq!(delta_apply(&base, &[0, 0, 0, 0, 2, 0, 1], 1), None);
+    }
+}
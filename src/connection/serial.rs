@@ -0,0 +1,198 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serial2::SerialPort;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct SerialSettings {
+    pub baud: u32,
+    #[serde(default)]
+    pub parity: Parity,
+    #[serde(default)]
+    pub stop_bits: StopBits,
+    // hardware RTS/CTS handshaking
+    #[serde(default)]
+    pub rts_cts: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StopBits {
+    #[default]
+    One,
+    Two,
+}
+
+// which serial device to open. usb selectors are looked up every time the
+// link reconnects, so the device can come back under a different path.
+// in a config file either a path string or a table with vid, pid and serial
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Port {
+    Path(Cow<'static, str>),
+    Usb {
+        vid: u16,
+        pid: u16,
+        serial: Option<Cow<'static, str>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortInfo {
+    pub path: PathBuf,
+    pub usb: Option<UsbInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+impl SerialSettings {
+    pub const fn new(baud: u32) -> SerialSettings {
+        SerialSettings {
+            baud,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            rts_cts: false,
+        }
+    }
+
+    pub fn open(&self, path: &Path) -> io::Result<SerialPort> {
+        let settings = *self;
+        // serial2 puts the port in raw mode itself
+        SerialPort::open(path, move |mut s: serial2::Settings| {
+            s.set_baud_rate(settings.baud)?;
+            s.set_char_size(serial2::CharSize::Bits8);
+            s.set_parity(match settings.parity {
+                Parity::None => serial2::Parity::None,
+                Parity::Odd => serial2::Parity::Odd,
+                Parity::Even => serial2::Parity::Even,
+            });
+            s.set_stop_bits(match settings.stop_bits {
+                StopBits::One => serial2::StopBits::One,
+                StopBits::Two => serial2::StopBits::Two,
+            });
+            s.set_flow_control(if settings.rts_cts {
+                serial2::FlowControl::RtsCts
+            } else {
+                serial2::FlowControl::None
+            });
+            Ok(s)
+        })
+    }
+}
+
+impl Port {
+    pub fn resolve(&self) -> io::Result<PathBuf> {
+        match self {
+            Port::Path(path) => Ok(PathBuf::from(path.as_ref())),
+            Port::Usb { .. } => discover_ports()?
+                .into_iter()
+                .find(|info| self.matches(info))
+                .map(|info| info.path)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {}", self))),
+        }
+    }
+
+    pub fn matches(&self, info: &PortInfo) -> bool {
+        match (self, &info.usb) {
+            (Port::Path(path), _) => info.path == Path::new(path.as_ref()),
+            (Port::Usb { vid, pid, serial }, Some(usb)) => {
+                usb.vid == *vid
+                    && usb.pid == *pid
+                    && serial
+                        .as_deref()
+                        .is_none_or(|s| usb.serial.as_deref() == Some(s))
+            }
+            (Port::Usb { .. }, None) => false,
+        }
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Port::Path(path) => write!(f, "{}", path),
+            Port::Usb { vid, pid, serial } => {
+                write!(f, "usb {:04x}:{:04x}", vid, pid)?;
+                if let Some(serial) = serial {
+                    write!(f, " serial {}", serial)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for PortInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(usb) = &self.usb {
+            write!(f, "  usb {:04x}:{:04x}", usb.vid, usb.pid)?;
+            for value in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                write!(f, "  {}", value)?;
+            }
+            if let Some(serial) = &usb.serial {
+                write!(f, "  serial {}", serial)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// every serial port on the system, with usb metadata where the os exposes it
+pub fn discover_ports() -> io::Result<Vec<PortInfo>> {
+    Ok(SerialPort::available_ports()?
+        .into_iter()
+        .map(|path| {
+            let usb = usb_info(&path);
+            PortInfo { path, usb }
+        })
+        .collect())
+}
+
+// linux keeps the usb descriptor in sysfs, a few directories above the tty
+fn usb_info(path: &Path) -> Option<UsbInfo> {
+    let name = path.file_name()?;
+    let device = Path::new("/sys/class/tty")
+        .join(name)
+        .join("device")
+        .canonicalize()
+        .ok()?;
+
+    let usb_device = device
+        .ancestors()
+        .take(4)
+        .find(|dir| dir.join("idVendor").exists())?;
+    let read = |file: &str| {
+        std::fs::read_to_string(usb_device.join(file))
+            .ok()
+            .map(|s| s.trim().to_owned())
+    };
+    let hex = |file: &str| u16::from_str_radix(&read(file)?, 16).ok();
+
+    Some(UsbInfo {
+        vid: hex("idVendor")?,
+        pid: hex("idProduct")?,
+        serial: read("serial"),
+        manufacturer: read("manufacturer"),
+        product: read("product"),
+    })
+}
@@ -1,35 +1,176 @@
-use crate::ScraenDim;
+// Defaults for everything config.rs reads from a config file, used as they
+// are when eyes runs without --config
+
+use std::borrow::Cow;
+
+use crate::scraen::mapping::{Lines, Serpentine};
+use crate::scraen::orientation::{Orientation, Rotation};
+use crate::scraen::blink::{BlinkSync, BlinkerDim};
+use crate::scraen::pupil::{Curve, PupilDim};
+use crate::scraen::style::StyleDim;
+use crate::scraen::tone::{Dither, ToneDim};
+use crate::connection::serial::{Parity, Port, SerialSettings, StopBits};
+use crate::connection::Encoding;
+use crate::{BlinkDim, ColorFormat, DetectorDim, Device, OutputDim, PixelMapping, ScraenDim};
 
 pub const PORT_NAME: &str = "/dev/ttyprintk";
 // const PORT_NAME: &str = "/dev/ttyACM0";
+pub const BAUD_RATE: u32 = 115200;
+// survives the controller being plugged into a different usb socket
+// const PORT: Port = Port::Usb { vid: 0x2e8a, pid: 0x000a, serial: None };
+pub const PORT: Port = Port::Path(Cow::Borrowed(PORT_NAME));
+
+pub const SERIAL_SETTINGS: SerialSettings = SerialSettings {
+    baud: BAUD_RATE,
+    parity: Parity::None,
+    stop_bits: StopBits::One,
+    rts_cts: false,
+};
+
+const SERIAL: Device = Device::Serial {
+    port: PORT,
+    settings: SERIAL_SETTINGS,
+    // Encoding::Rle or Encoding::Delta fit more panels on the link
+    encoding: Encoding::Raw,
+};
+// const ARTNET: Device = Device::ArtNet { target: Cow::Borrowed("127.0.0.1") };
+
+// sends every panel to one place instead of its own output,
+// Some(Device::Null) runs the installation with no hardware attached
+pub const OUTPUT_OVERRIDE: Option<Device> = None;
+// pub const OUTPUT_OVERRIDE: Option<Device> = Some(Device::File { path: Cow::Borrowed("panels.bin") });
+
+// records everything sent to the panels, replay with `eyes replay <file>`.
+// {time} is replaced with the start time, a .csv extension writes csv
+pub const RECORD_TO: Option<&str> = None;
+// pub const RECORD_TO: Option<&str> = Some("recordings/{time}.eyrec");
+
+// rows snake back and forth starting from the right, with a blank byte between rows
+pub const SERPENTINE_ROWS: PixelMapping = PixelMapping::Lines(Lines {
+    serpentine: Serpentine::EvenReversed,
+    gap: 1,
+    ..Lines::ROW_MAJOR
+});
 
-pub const SCRAEN_SCALE: f32 = 10.0 * SCALE;
+// cap on every panel, the old firmware protocol topped out around 200/255
+pub const BRIGHTNESS_LIMIT: f32 = 0.8;
+
+pub const DEFAULT_TONE: ToneDim = ToneDim {
+    gamma: 2.2,
+    brightness: BRIGHTNESS_LIMIT,
+    current_budget_ma: None,
+    ma_per_channel: 20.0,
+    dither: Dither::Temporal,
+};
+
+pub const DEFAULT_BLINK: BlinkDim = BlinkDim {
+    shutting_time: 0.2,
+    closed_time: 0.1,
+    opening_time: 0.1,
+};
+
+// about one blink every four seconds, like people
+pub const BLINKER: BlinkerDim = BlinkerDim {
+    sync: BlinkSync::Staggered,
+    interval_sec: 4.0,
+    min_interval_sec: 0.8,
+    stagger_sec: 0.05,
+    double_chance: 0.1,
+    half_chance: 0.1,
+    wink_chance: 0.05,
+    half_depth: 0.5,
+};
+
+// dark rooms and close faces open the pupil
+pub const DEFAULT_PUPIL: PupilDim = PupilDim {
+    light: Curve(Cow::Borrowed(&[(0.0, 1.0), (0.6, 0.0)])),
+    face: Curve(Cow::Borrowed(&[(0.1, 0.0), (0.5, 0.4)])),
+    constrict_sec: 0.3,
+    dilate_sec: 1.5,
+};
 
 pub const SCRAENS: [ScraenDim; 4] = [
     ScraenDim {
         rez: 4,
         xy: (466.0, -123.0),
         wh: (4.0, 4.0),
-        //need to be rotated 180
+        // hung upside down
+        orientation: Orientation {
+            rotation: Rotation::R180,
+            ..Orientation::UPRIGHT
+        },
+        output: OutputDim {
+            device: SERIAL,
+            address: 0,
+        },
+        mapping: SERPENTINE_ROWS,
+        format: ColorFormat::Mono,
+        tone: DEFAULT_TONE,
+        blink: DEFAULT_BLINK,
+        style: StyleDim::Disc,
+        pupil: DEFAULT_PUPIL,
+        test_pattern: None,
     },
     ScraenDim {
         rez: 16,
         xy: (102.0, -212.0),
         wh: (16.0, 16.0),
+        orientation: Orientation::UPRIGHT,
+        output: OutputDim {
+            device: SERIAL,
+            address: 1,
+        },
+        mapping: SERPENTINE_ROWS,
+        format: ColorFormat::Mono,
+        tone: DEFAULT_TONE,
+        blink: DEFAULT_BLINK,
+        style: StyleDim::Disc,
+        pupil: DEFAULT_PUPIL,
+        test_pattern: None,
     },
     ScraenDim {
         rez: 8,
         xy: (38.0, 92.0),
         wh: (8.0, 8.0),
-        //need to be rotated 180
+        // hung upside down
+        orientation: Orientation {
+            rotation: Rotation::R180,
+            ..Orientation::UPRIGHT
+        },
+        output: OutputDim {
+            device: SERIAL,
+            address: 2,
+        },
+        mapping: SERPENTINE_ROWS,
+        format: ColorFormat::Mono,
+        tone: DEFAULT_TONE,
+        blink: DEFAULT_BLINK,
+        style: StyleDim::Disc,
+        pupil: DEFAULT_PUPIL,
+        test_pattern: None,
     },
     ScraenDim {
         rez: 12,
         xy: (453.0, 124.0),
         wh: (12.0, 12.0),
+        orientation: Orientation::UPRIGHT,
+        output: OutputDim {
+            device: SERIAL,
+            address: 3,
+        },
+        mapping: SERPENTINE_ROWS,
+        format: ColorFormat::Mono,
+        tone: DEFAULT_TONE,
+        blink: DEFAULT_BLINK,
+        style: StyleDim::Disc,
+        pupil: DEFAULT_PUPIL,
+        test_pattern: None,
     },
 ];
 
+// panels are refreshed at this rate by the output thread, independent of the window
+pub const OUTPUT_HZ: f64 = 60.0;
+
 pub const OSC_PORT: u16 = 8338;
 
 pub const SCALE: f32 = 2.5;
@@ -44,3 +185,10 @@ pub const HEIGHT: f32 = 360.0 * 1.0;
 pub const SHOWDEBUG: bool = true;
 
 pub const WEBCAMS_INDEX: [usize; 2] = [0, 0];
+
+pub const DETECTOR: DetectorDim = DetectorDim {
+    min_face_size: 40,
+    score_thresh: 1.0,
+    pyramid_scale_factor: 0.1,
+    slide_window_step: (4, 4),
+};
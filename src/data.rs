@@ -1,6 +1,14 @@
 use chrono;
 
-use nannou::{prelude::WHITE, Draw};
+use nannou::{
+    prelude::{Rect, GREY, RED, WHITE},
+    Draw,
+};
+
+use crate::connection::{FrameStats, PanelHealth};
+
+// how long a panel can stay quiet before it is drawn as lost
+const HEALTH_TIMEOUT_SEC: f32 = 2.0;
 
 pub fn draw_text(draw: &Draw) {
     let dt = chrono::offset::Local::now();
@@ -15,3 +23,43 @@ pub fn draw_text(draw: &Draw) {
     .w_h(800.0, 10.0)
     .x_y(0.0, -370.0);
 }
+
+pub fn draw_health(draw: &Draw, rect: Rect, health: Option<&PanelHealth>) {
+    let (text, color) = match health {
+        Some(h) => {
+            let mut text = format!("ack {} nak {}", h.acks, h.naks);
+            if let Some(t) = h.telemetry {
+                text += &format!(
+                    "\n{:.0}Hz {:.2}V {:.1}C",
+                    t.refresh_hz, t.supply_volts, t.temperature_c
+                );
+            }
+            if h.last_heard.elapsed().as_secs_f32() > HEALTH_TIMEOUT_SEC {
+                (text, RED)
+            } else {
+                (text, WHITE)
+            }
+        }
+        None => ("no reply".to_owned(), GREY),
+    };
+
+    draw.text(&text)
+        .color(color)
+        .font_size(10)
+        .w(rect.w().abs().max(80.0))
+        .x_y(rect.x(), rect.bottom().min(rect.top()) - 14.0);
+}
+
+pub fn draw_output_stats(draw: &Draw, stats: FrameStats) {
+    draw.text(
+        format!(
+            "output {}Hz  sent {}  dropped {}  repeated {}",
+            stats.refresh_hz, stats.sent, stats.dropped, stats.duplicated
+        )
+        .as_str(),
+    )
+    .color(GREY)
+    .font_size(12)
+    .w_h(800.0, 10.0)
+    .x_y(0.0, -340.0);
+}
@@ -0,0 +1,237 @@
+use nannou::prelude::*;
+
+use crate::config::Config;
+use crate::scraen::{Scraen, PIXEL_SIZE};
+
+// panel centres and corners land on this grid, in window points
+const GRID: f32 = 5.0;
+// how close an edge has to come to another panel's edge to line up with it
+const SNAP_DISTANCE: f32 = 8.0;
+// the resize handle in the bottom right corner of the selected panel
+const HANDLE: f32 = 10.0;
+// where the layout goes when eyes was started without --config
+pub const SAVE_PATH: &str = "eyes.toml";
+
+// Edit mode for placing panels over the camera image, toggled with E.
+// Drag a panel to move it and its bottom right corner to resize it. Tab
+// selects the next panel, the arrows nudge it (shift for 10), R turns it a
+// quarter clockwise, X and Y mirror it, G turns snapping off and on and S
+// saves the layout to the config file. Saving writes the whole config out
+// again, so comments in the file are lost.
+pub struct LayoutEditor {
+    pub active: bool,
+    selected: Option<usize>,
+    drag: Option<Drag>,
+    snap: bool,
+}
+
+enum Drag {
+    // where the panel was grabbed, from its centre
+    Move(Vec2),
+    // the top left corner, which stays put
+    Resize(Vec2),
+}
+
+// what the caller has to do after an edit
+pub enum Edit {
+    // xy or wh changed, Scraen::set_layout is enough
+    Moved(usize),
+    // orientation changed, needs a rebuilt Scraen
+    Reoriented(usize),
+    Save,
+}
+
+impl LayoutEditor {
+    pub fn new() -> LayoutEditor {
+        LayoutEditor {
+            active: false,
+            selected: None,
+            drag: None,
+            snap: true,
+        }
+    }
+
+    // drops the selection when a reload takes its panel away
+    pub fn retain(&mut self, len: usize) {
+        if self.selected.is_some_and(|i| i >= len) {
+            self.selected = None;
+            self.drag = None;
+        }
+    }
+
+    pub fn key_pressed(&mut self, key: Key, shift: bool, config: &mut Config) -> Option<Edit> {
+        if key == Key::E {
+            self.active = !self.active;
+            self.drag = None;
+            println!("layout editor {}", if self.active { "on" } else { "off" });
+            return None;
+        }
+        if !self.active {
+            return None;
+        }
+
+        let count = config.scraens.len();
+        let step = if shift { 10.0 } else { 1.0 };
+        let nudge = match key {
+            Key::Tab => {
+                self.selected = Some(self.selected.map_or(0, |i| (i + 1) % count));
+                return None;
+            }
+            Key::G => {
+                self.snap = !self.snap;
+                return None;
+            }
+            Key::S => return Some(Edit::Save),
+            Key::R | Key::X | Key::Y => {
+                let i = self.selected?;
+                let orientation = &mut config.scraens.get_mut(i)?.orientation;
+                match key {
+                    Key::R => orientation.rotation = orientation.rotation.next(),
+                    Key::X => orientation.mirror_x = !orientation.mirror_x,
+                    _ => orientation.mirror_y = !orientation.mirror_y,
+                }
+                return Some(Edit::Reoriented(i));
+            }
+            Key::Left => (-step, 0.0),
+            Key::Right => (step, 0.0),
+            Key::Up => (0.0, step),
+            Key::Down => (0.0, -step),
+            _ => return None,
+        };
+
+        let i = self.selected?;
+        let xy = &mut config.scraens.get_mut(i)?.xy;
+        *xy = (xy.0 + nudge.0, xy.1 + nudge.1);
+        Some(Edit::Moved(i))
+    }
+
+    pub fn mouse_pressed(&mut self, pos: Point2, scraens: &[Scraen]) {
+        if !self.active {
+            return;
+        }
+        // the last panel is drawn on top, so it gets the click
+        let hit = scraens
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, s)| (i, s.draw_rect().absolute()))
+            .find(|(_, rect)| rect.pad(-HANDLE).contains(pos));
+
+        self.selected = hit.map(|(i, _)| i);
+        self.drag = hit.map(|(_, rect)| {
+            if pos.distance(rect.bottom_right()) < HANDLE {
+                Drag::Resize(rect.top_left())
+            } else {
+                Drag::Move(pos - rect.xy())
+            }
+        });
+    }
+
+    pub fn mouse_released(&mut self) {
+        self.drag = None;
+    }
+
+    pub fn mouse_moved(
+        &mut self,
+        pos: Point2,
+        config: &mut Config,
+        scraens: &[Scraen],
+    ) -> Option<Edit> {
+        let drag = self.drag.as_ref()?;
+        let i = self.selected?;
+        let rect = scraens.get(i)?.draw_rect().absolute();
+        // edges of every other panel, for lining up with them
+        let others: Vec<Rect> = scraens
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, s)| s.draw_rect().absolute())
+            .collect();
+        let xs: Vec<f32> = others.iter().flat_map(|r| [r.left(), r.x(), r.right()]).collect();
+        let ys: Vec<f32> = others.iter().flat_map(|r| [r.bottom(), r.y(), r.top()]).collect();
+
+        let dim = config.scraens.get_mut(i)?;
+        match drag {
+            Drag::Move(grab) => {
+                let centre = pos - *grab;
+                let (hw, hh) = (rect.w() / 2.0, rect.h() / 2.0);
+                let dx = self.snap_axis(&[centre.x, centre.x - hw, centre.x + hw], &xs);
+                let dy = self.snap_axis(&[centre.y, centre.y - hh, centre.y + hh], &ys);
+                dim.xy = (centre.x + dx, centre.y + dy);
+            }
+            Drag::Resize(top_left) => {
+                let corner = pos + vec2(self.snap_axis(&[pos.x], &xs), self.snap_axis(&[pos.y], &ys));
+                // window size of one wh unit, and the smallest a panel can get
+                let unit = PIXEL_SIZE * config.scale;
+                let w = (corner.x - top_left.x).max(unit);
+                let h = (top_left.y - corner.y).max(unit);
+                dim.xy = (top_left.x + w / 2.0, top_left.y - h / 2.0);
+                // wh is in the panel's own frame
+                dim.wh = dim.orientation.footprint((w / unit, h / unit));
+            }
+        }
+        Some(Edit::Moved(i))
+    }
+
+    // how far to shift so one of `edges` lines up with one of `targets`,
+    // or failing that so the first edge sits on the grid
+    fn snap_axis(&self, edges: &[f32], targets: &[f32]) -> f32 {
+        if !self.snap {
+            return 0.0;
+        }
+        edges
+            .iter()
+            .flat_map(|e| targets.iter().map(move |t| t - e))
+            .filter(|d| d.abs() < SNAP_DISTANCE)
+            .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or_else(|| (edges[0] / GRID).round() * GRID - edges[0])
+    }
+
+    pub fn draw(&self, draw: &Draw, config: &Config, scraens: &[Scraen]) {
+        for (i, (scraen, dim)) in scraens.iter().zip(&config.scraens).enumerate() {
+            let rect = scraen.draw_rect().absolute();
+            let selected = self.selected == Some(i);
+            let color = if selected { YELLOW } else { GREY };
+
+            draw.rect()
+                .xy(rect.xy())
+                .wh(rect.wh())
+                .no_fill()
+                .stroke(color)
+                .stroke_weight(1.0);
+            if selected {
+                draw.rect()
+                    .xy(rect.bottom_right())
+                    .w_h(HANDLE, HANDLE)
+                    .color(YELLOW);
+            }
+
+            let o = dim.orientation;
+            let readout = format!(
+                "{}  xy {:.0}, {:.0}  wh {:.1} x {:.1}  {}°{}{}",
+                i,
+                dim.xy.0,
+                dim.xy.1,
+                dim.wh.0,
+                dim.wh.1,
+                o.rotation.degrees(),
+                if o.mirror_x { "  mirror x" } else { "" },
+                if o.mirror_y { "  mirror y" } else { "" }
+            );
+            draw.text(&readout)
+                .color(color)
+                .font_size(10)
+                .w(rect.w().max(160.0))
+                .x_y(rect.x(), rect.top() + 10.0);
+        }
+
+        draw.text(&format!(
+            "layout  drag move  corner resize  tab select  arrows nudge  r turn  x y mirror  g snap {}  s save (drops comments)",
+            if self.snap { "on" } else { "off" }
+        ))
+        .color(YELLOW)
+        .font_size(12)
+        .w_h(800.0, 10.0)
+        .x_y(0.0, 370.0);
+    }
+}
@@ -0,0 +1,19 @@
+// The parts of eyes other binaries share. The simulator decodes the same
+// packets and recordings, and lays pixels out with the same mappings, so it
+// can't drift from what the app sends.
+//
+// each module keeps its place in the tree, main.rs picks them up from here
+
+pub mod connection {
+    pub mod protocol;
+}
+
+pub mod recording {
+    pub mod format;
+}
+
+pub mod scraen {
+    pub mod color;
+    pub mod mapping;
+    pub mod orientation;
+}
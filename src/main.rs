@@ -1,31 +1,73 @@
 #![allow(dead_code)]
 // #![allow(unused_imports)]
 
+use anyhow::{Context, Result};
+use clap::Parser;
 use nannou::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 pub mod connection;
-use crate::connection::Connection;
+use crate::connection::serial::Port;
+use crate::connection::{Connection, OutputThread};
 mod data;
-use data::draw_text;
+use data::{draw_health, draw_output_stats, draw_text};
 mod scraen;
-use scraen::Scraen;
+use scraen::tone::ToneDim;
+use scraen::{
+    BlinkDim, ColorFormat, Orientation, PixelMapping, PupilDim, Scraen, StyleDim, TestPattern,
+};
 mod vision;
-use vision::Vision;
+use vision::{DetectorDim, Vision};
 mod timer;
 mod walk;
 use walk::Walk;
+mod network;
+mod output;
+use output::{Device, OutputDim, Outputs};
+mod recording;
+use recording::Recorder;
+mod config;
+use config::{Config, ConfigWatcher};
+mod editor;
+use editor::{Edit, LayoutEditor};
+mod cli;
+use cli::{Cli, Command};
+mod behaviour;
+use behaviour::Behaviour;
+use scraen::blink::Blinker;
 pub mod constants;
 use constants::*;
 
 pub use serial2::SerialPort;
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ScraenDim {
     rez: u32,
     xy: (f32, f32),
     wh: (f32, f32),
-    rotate: bool,
+    #[serde(default)]
+    orientation: Orientation,
+    output: OutputDim,
+    #[serde(default = "config::default_mapping")]
+    mapping: PixelMapping,
+    #[serde(default = "config::default_format")]
+    format: ColorFormat,
+    #[serde(default)]
+    tone: ToneDim,
+    #[serde(default)]
+    blink: BlinkDim,
+    #[serde(default)]
+    style: StyleDim,
+    #[serde(default)]
+    pupil: PupilDim,
+    // shown instead of the eye, for commissioning
+    #[serde(default)]
+    test_pattern: Option<TestPattern>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Settings {
     min_radius: f32,
     max_radius: f32,
@@ -33,13 +75,42 @@ pub struct Settings {
 }
 
 fn main() {
-    nannou::app(model).update(update).run();
+    match Cli::parse().command() {
+        Command::ListSerial => Connection::print_avaliable_ports(),
+        Command::ListCameras => vision::print_cameras(),
+        Command::Replay {
+            recording,
+            port,
+            baud,
+        } => {
+            let port = port.map_or(PORT, |p| Port::Path(Cow::Owned(p)));
+            let mut settings = SERIAL_SETTINGS;
+            if let Some(baud) = baud {
+                settings.baud = baud;
+            }
+            if let Err(err) = recording::replay(&recording, port, settings) {
+                println!("replay failed: {:#}", err);
+            }
+        }
+        // the nannou app parses the command line again in model
+        Command::Run { .. } | Command::TestPattern { .. } | Command::Calibrate { .. } => {
+            nannou::app(model).update(update).run()
+        }
+    }
 }
 pub struct Model {
     scraens: Vec<Scraen>,
     vision: Vision,
     // vision2: Vision,
-    port: Connection,
+    output: OutputThread,
+    config: Config,
+    watcher: Option<ConfigWatcher>,
+    editor: LayoutEditor,
+    behaviour: Behaviour,
+    blinker: Blinker,
+    // from `eyes test-pattern`, over whatever the config says. without a
+    // panel every panel shows the pattern, with one the rest stay blank
+    test_pattern: Option<(Option<usize>, TestPattern)>,
 
     camera_rect: Rect,
     target: Vec2,
@@ -48,10 +119,43 @@ pub struct Model {
 }
 
 fn model(app: &App) -> Model {
+    let command = Cli::parse().command();
+    let mut config = Config::load_or_default(command.config()).unwrap_or_else(|err| {
+        println!("{:#}", err);
+        std::process::exit(1);
+    });
+    let watcher = command
+        .config()
+        .map(|path| ConfigWatcher::new(path, app.time));
+
+    let mut editor = LayoutEditor::new();
+    let test_pattern = match command {
+        Command::TestPattern {
+            panel: Some(panel), ..
+        } if panel >= config.scraens.len() => {
+            println!("there is no panel {}, the config has {}", panel, config.scraens.len());
+            std::process::exit(1);
+        }
+        Command::TestPattern { panel, pattern, .. } => Some((panel, pattern)),
+        Command::Calibrate { .. } => {
+            editor.active = true;
+            config.show_debug = true;
+            None
+        }
+        _ => None,
+    };
+
     let window_id = app
         .new_window()
-        .size((WIDTH * SCALE) as u32, (HEIGHT * SCALE) as u32)
+        .size(
+            (config.window_wh.0 * config.scale) as u32,
+            (config.window_wh.1 * config.scale) as u32,
+        )
         .view(view)
+        .key_pressed(key_pressed)
+        .mouse_pressed(mouse_pressed)
+        .mouse_released(mouse_released)
+        .mouse_moved(mouse_moved)
         .build()
         .unwrap();
     let window = app.window(window_id).unwrap();
@@ -60,29 +164,39 @@ fn model(app: &App) -> Model {
 
     let camera_rect = win_rect;
 
-    let mut screen = Vec::new();
-    for scraen_dim in SCRAENS {
-        screen.push(Scraen::new(app, scraen_dim, camera_rect));
-    }
-
-    let mut port = Connection::new(PORT_NAME, true);
-    port.open_port();
+    let screen: Vec<Scraen> = (0..config.scraens.len())
+        .map(|i| build_scraen(app, &config, i, camera_rect))
+        .collect::<Result<_>>()
+        .unwrap_or_else(|err| {
+            println!("{:#}", err);
+            std::process::exit(1);
+        });
+    let output = spawn_output(&config, &screen);
     Connection::print_avaliable_ports();
 
     let mut vision = Vision::new(
         app,
-        CAMERA_WH,
+        config.camera_wh,
         camera_rect,
-        WEBCAMS_INDEX,
+        config.webcams_index,
         // [(0, face_cam_rect), (0, street_cam_rect)],
+        config.detector,
     );
 
     vision.update(app);
+    let behaviour = Behaviour::new(config.osc_port);
+    let blinker = Blinker::new(config.blinks);
 
     Model {
         scraens: screen,
         vision,
-        port,
+        output,
+        config,
+        watcher,
+        editor,
+        behaviour,
+        blinker,
+        test_pattern,
         camera_rect,
         target: vec2(0.0, 0.0),
         walk_x: Walk::new(43324),
@@ -90,9 +204,148 @@ fn model(app: &App) -> Model {
     }
 }
 
+fn build_scraen(app: &App, config: &Config, i: usize, camera_rect: Rect) -> Result<Scraen> {
+    let mut scraen_dim = config.scraens[i].clone();
+    if let Some(device) = &config.output_override {
+        scraen_dim.output.device = device.clone();
+    }
+    Scraen::new(app, scraen_dim, camera_rect, config.scale)
+        .with_context(|| format!("scraens[{}]", i))
+}
+
+fn spawn_output(config: &Config, scraens: &[Scraen]) -> OutputThread {
+    let mut outputs = Outputs::new(scraens.iter().map(|s| s.output()));
+    if let Some(path) = &config.record_to {
+        match Recorder::create(path) {
+            Ok(recorder) => outputs.record_to(recorder),
+            Err(err) => println!("not recording: {:#}", err),
+        }
+    }
+    OutputThread::spawn(outputs, config.output_hz)
+}
+
+// swaps in a config saved while running. only the scraens whose dims changed
+// are rebuilt, and the outputs are reopened only when where panels go changed.
+// the cameras are never touched. a panel that can't be built keeps the running
+// config, like a file that doesn't validate
+fn apply_config(app: &App, model: &mut Model, config: Config) {
+    let running = &model.config;
+    let rebuild_all =
+        config.scale != running.scale || config.output_override != running.output_override;
+    // rebuilt panels pick up the wall's expression instead of starting neutral
+    let expression = model.behaviour.current();
+    let mut rebuilt = Vec::new();
+    for i in 0..config.scraens.len() {
+        if rebuild_all || running.scraens.get(i) != Some(&config.scraens[i]) {
+            match build_scraen(app, &config, i, model.camera_rect) {
+                Ok(mut scraen) => {
+                    scraen.set_expression(expression, app.time.into(), 0.0);
+                    rebuilt.push((i, scraen));
+                }
+                Err(err) => {
+                    println!("keeping the running config: {:#}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    let old = std::mem::replace(&mut model.config, config);
+    let config = &model.config;
+    model.scraens.truncate(config.scraens.len());
+    // new panels come in order after the kept ones
+    for (i, scraen) in rebuilt {
+        if i < model.scraens.len() {
+            model.scraens[i] = scraen;
+        } else {
+            model.scraens.push(scraen);
+        }
+    }
+    model.editor.retain(model.scraens.len());
+
+    let outputs = |c: &Config| -> Vec<OutputDim> {
+        c.scraens.iter().map(|s| s.output.clone()).collect()
+    };
+    if outputs(config) != outputs(&old)
+        || config.output_override != old.output_override
+        || config.record_to != old.record_to
+        || config.output_hz != old.output_hz
+    {
+        // the old thread has to let go of its ports before the new one opens them
+        model.output.shutdown();
+        model.output = spawn_output(config, &model.scraens);
+    }
+
+    model.blinker.set_dim(config.blinks);
+    if config.detector != old.detector {
+        model.vision.set_detector(config.detector);
+    }
+    if config.camera_wh != old.camera_wh
+        || config.webcams_index != old.webcams_index
+        || config.window_wh != old.window_wh
+        || config.osc_port != old.osc_port
+    {
+        println!("camera, window and osc changes apply after a restart");
+    }
+}
+
+fn key_pressed(app: &App, model: &mut Model, key: Key) {
+    let edit = model
+        .editor
+        .key_pressed(key, app.keys.mods.shift(), &mut model.config);
+    apply_edit(app, model, edit);
+}
+
+fn mouse_pressed(app: &App, model: &mut Model, button: MouseButton) {
+    if button == MouseButton::Left {
+        model
+            .editor
+            .mouse_pressed(app.mouse.position(), &model.scraens);
+    }
+}
+
+fn mouse_released(_app: &App, model: &mut Model, _button: MouseButton) {
+    model.editor.mouse_released();
+}
+
+fn mouse_moved(app: &App, model: &mut Model, pos: Point2) {
+    let edit = model
+        .editor
+        .mouse_moved(pos, &mut model.config, &model.scraens);
+    apply_edit(app, model, edit);
+}
+
+fn apply_edit(app: &App, model: &mut Model, edit: Option<Edit>) {
+    match edit {
+        Some(Edit::Moved(i)) => {
+            let dim = &model.config.scraens[i];
+            model.scraens[i].set_layout(dim.xy, dim.wh, model.config.scale);
+        }
+        Some(Edit::Reoriented(i)) => match build_scraen(app, &model.config, i, model.camera_rect) {
+            Ok(scraen) => model.scraens[i] = scraen,
+            Err(err) => println!("couldn't reorient: {:#}", err),
+        },
+        Some(Edit::Save) => {
+            let path = model
+                .watcher
+                .as_ref()
+                .map_or(editor::SAVE_PATH, |w| w.path());
+            if let Err(err) = model.config.save(path) {
+                println!("couldn't save the layout: {:#}", err);
+            }
+        }
+        None => {}
+    }
+}
+
 fn update(app: &App, model: &mut Model, _update: Update) {
     let time = app.time;
 
+    let reloaded = model.watcher.as_mut().and_then(|w| w.poll(time));
+    if let Some(config) = reloaded {
+        apply_config(app, model, config);
+    }
+
     let target = model.vision.update(app);
 
     if let Some(t) = target {
@@ -106,17 +359,33 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     model.walk_x.update();
     model.walk_y.update();
 
-    model.port.write(vec![255]);
-    for screen in &mut model.scraens {
-        screen.draw_eye();
+    let scene = model.vision.scene();
+    model.blinker.update(&mut model.scraens, time.into());
+    if let Some(change) = model.behaviour.update(&scene, time.into()) {
+        for screen in &mut model.scraens {
+            screen.set_expression(change.expression, time.into(), change.duration);
+        }
+    }
+    let mut frame = Vec::new();
+    for (i, screen) in model.scraens.iter_mut().enumerate() {
+        let pattern = match model.test_pattern {
+            Some((Some(panel), pattern)) if panel == i => Some(pattern),
+            Some((Some(_), _)) => Some(TestPattern::Blank),
+            Some((None, pattern)) => Some(pattern),
+            None => model.config.scraens[i].test_pattern,
+        };
+        screen.set_pattern(pattern, i, time.into());
+        if !screen.showing_pattern() {
+            screen.draw_eye(time.into());
+        }
         screen.render_texture(&app);
 
-        screen.update(&app, model.target, time.into());
-        if let Some(buf) = screen.serial_packet() {
-            model.port.write(buf);
+        screen.update(model.target, &scene, time.into());
+        if let Some(panel) = screen.serial_packet() {
+            frame.push((screen.output().clone(), panel));
         }
     }
-    model.port.write(vec![254]);
+    model.output.submit(frame);
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
@@ -126,16 +395,22 @@ fn view(app: &App, model: &Model, frame: Frame) {
     model.vision.draw_camera(&draw);
     model.vision.draw_face(&draw, model.camera_rect);
 
-    if SHOWDEBUG {
+    if model.config.show_debug || model.editor.active {
         for screen in &model.scraens {
             screen.draw_to_frame(&draw);
+            let health = model.output.health(screen.output());
+            draw_health(&draw, screen.draw_rect(), health.as_ref());
         }
     }
+    if model.editor.active {
+        model.editor.draw(&draw, &model.config, &model.scraens);
+    }
 
     // let target = model.vision.biggest_face.xy();
     let walk = vec2(model.walk_x.val(), model.walk_y.val()) - model.camera_rect.xy();
-    if SHOWDEBUG {
+    if model.config.show_debug {
         draw.ellipse().xy(walk).radius(30.0).color(GREY);
+        draw_output_stats(&draw, model.output.stats());
     }
 
     draw_text(&draw);
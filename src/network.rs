@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use nannou::rand::random;
+
+use crate::output::{OutputSink, PanelFrame};
+
+// Ethernet pixel controllers. A panel starts at the universe in its
+// OutputDim address and spills into the following universes, without
+// splitting a pixel across two of them. Sends are non-blocking, a full socket
+// buffer drops the packet like a lost UDP datagram would.
+//
+// to look at the output on one machine point the target at 127.0.0.1 and run
+// any Art-Net or sACN monitor on the default port
+
+const DMX_CHANNELS: usize = 512;
+
+const ARTNET_PORT: u16 = 6454;
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+const ARTNET_OP_DMX: u16 = 0x5000;
+const ARTNET_OP_SYNC: u16 = 0x5200;
+const ARTNET_VERSION: u16 = 14;
+
+// net, sub-net and universe together are 15 bits
+pub const ARTNET_MAX_UNIVERSE: u16 = 0x7FFF;
+
+const SACN_PORT: u16 = 5568;
+// universe 0 is reserved
+pub const SACN_MIN_UNIVERSE: u16 = 1;
+pub const SACN_MAX_UNIVERSE: u16 = 63999;
+const SACN_PRIORITY: u8 = 100;
+const SOURCE_NAME: &str = "eyes";
+
+pub struct ArtNet {
+    socket: UdpSocket,
+    target: SocketAddr,
+    sequence: u8,
+}
+
+pub struct Sacn {
+    socket: UdpSocket,
+    // None sends each universe to its standard multicast group
+    target: Option<SocketAddr>,
+    cid: [u8; 16],
+    sequences: HashMap<u16, u8>,
+}
+
+impl ArtNet {
+    pub fn new(target: &str) -> io::Result<ArtNet> {
+        let target = resolve(target, ARTNET_PORT)?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(ArtNet {
+            socket,
+            target,
+            sequence: 0,
+        })
+    }
+
+    fn send(&self, packet: &[u8]) {
+        match self.socket.send_to(packet, self.target) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => println!("art-net send to {} failed: {}", self.target, err),
+        }
+    }
+}
+
+impl OutputSink for ArtNet {
+    fn send_panel(&mut self, panel: &PanelFrame) {
+        // 0 tells receivers not to reorder, so skip it when wrapping
+        self.sequence = self.sequence.checked_add(1).unwrap_or(1);
+
+        for (i, chunk) in universe_chunks(panel).enumerate() {
+            let universe = match chunk_universe(panel, i) {
+                Some(universe) => universe,
+                None => break,
+            };
+            // DMX length has to be even
+            let len = chunk.len() + chunk.len() % 2;
+
+            let mut packet = Vec::with_capacity(18 + len);
+            packet.extend_from_slice(ARTNET_ID);
+            packet.extend_from_slice(&ARTNET_OP_DMX.to_le_bytes());
+            packet.extend_from_slice(&ARTNET_VERSION.to_be_bytes());
+            packet.push(self.sequence);
+            packet.push(0); // physical port
+            packet.push((universe & 0xFF) as u8); // sub-net and universe
+            packet.push(((universe >> 8) & 0x7F) as u8); // net
+            packet.extend_from_slice(&(len as u16).to_be_bytes());
+            packet.extend_from_slice(chunk);
+            packet.resize(18 + len, 0);
+            self.send(&packet);
+        }
+    }
+
+    // ArtSync, controllers that support it latch every universe at once
+    fn end_frame(&mut self) {
+        let mut packet = Vec::with_capacity(14);
+        packet.extend_from_slice(ARTNET_ID);
+        packet.extend_from_slice(&ARTNET_OP_SYNC.to_le_bytes());
+        packet.extend_from_slice(&ARTNET_VERSION.to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.send(&packet);
+    }
+}
+
+impl Sacn {
+    // "multicast" sends every universe to 239.255.<hi>.<lo>
+    pub fn new(target: &str) -> io::Result<Sacn> {
+        let target = match target {
+            "multicast" => None,
+            t => Some(resolve(t, SACN_PORT)?),
+        };
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Sacn {
+            socket,
+            target,
+            cid: random(),
+            sequences: HashMap::new(),
+        })
+    }
+
+    fn packet(&mut self, universe: u16, data: &[u8]) -> Vec<u8> {
+        let sequence = self.sequences.entry(universe).or_insert(0);
+        *sequence = sequence.wrapping_add(1);
+
+        let total = 126 + data.len();
+        let flags_len = |from: usize| (0x7000 | (total - from) as u16).to_be_bytes();
+
+        let mut packet = Vec::with_capacity(total);
+        // root layer
+        packet.extend_from_slice(&0x0010u16.to_be_bytes());
+        packet.extend_from_slice(&0x0000u16.to_be_bytes());
+        packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+        packet.extend_from_slice(&flags_len(16));
+        packet.extend_from_slice(&0x0000_0004u32.to_be_bytes());
+        packet.extend_from_slice(&self.cid);
+        // framing layer
+        packet.extend_from_slice(&flags_len(38));
+        packet.extend_from_slice(&0x0000_0002u32.to_be_bytes());
+        let mut name = [0u8; 64];
+        name[..SOURCE_NAME.len()].copy_from_slice(SOURCE_NAME.as_bytes());
+        packet.extend_from_slice(&name);
+        packet.push(SACN_PRIORITY);
+        packet.extend_from_slice(&0u16.to_be_bytes()); // sync address
+        packet.push(*sequence);
+        packet.push(0); // options
+        packet.extend_from_slice(&universe.to_be_bytes());
+        // dmp layer
+        packet.extend_from_slice(&flags_len(115));
+        packet.push(0x02);
+        packet.push(0xA1);
+        packet.extend_from_slice(&0x0000u16.to_be_bytes()); // first property address
+        packet.extend_from_slice(&0x0001u16.to_be_bytes()); // address increment
+        packet.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        packet.push(0x00); // dmx start code
+        packet.extend_from_slice(data);
+        packet
+    }
+}
+
+impl OutputSink for Sacn {
+    fn send_panel(&mut self, panel: &PanelFrame) {
+        for (i, chunk) in universe_chunks(panel).enumerate() {
+            let universe = match chunk_universe(panel, i) {
+                Some(universe) => universe,
+                None => break,
+            };
+            let packet = self.packet(universe, chunk);
+            let target = self.target.unwrap_or_else(|| {
+                let [hi, lo] = universe.to_be_bytes();
+                SocketAddr::from(([239, 255, hi, lo], SACN_PORT))
+            });
+
+            match self.socket.send_to(&packet, target) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => println!("sacn send to {} failed: {}", target, err),
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {}
+}
+
+fn universe_chunks(panel: &PanelFrame) -> std::slice::Chunks<'_, u8> {
+    panel
+        .data
+        .chunks(universe_bytes(panel.format.bytes_per_pixel()))
+}
+
+// Config::validate keeps panels inside the universe range, this only stops
+// the sum wrapping around if one gets through anyway
+fn chunk_universe(panel: &PanelFrame, i: usize) -> Option<u16> {
+    let universe = u16::try_from(i)
+        .ok()
+        .and_then(|i| panel.address.checked_add(i));
+    if universe.is_none() {
+        println!("panel {} runs past the last universe", panel.address);
+    }
+    universe
+}
+
+fn universe_bytes(bytes_per_pixel: usize) -> usize {
+    DMX_CHANNELS / bytes_per_pixel * bytes_per_pixel
+}
+
+// how many universes a panel of `bytes` spills over
+pub fn universe_count(bytes: usize, bytes_per_pixel: usize) -> usize {
+    let per_universe = universe_bytes(bytes_per_pixel);
+    bytes.div_ceil(per_universe).max(1)
+}
+
+fn resolve(target: &str, default_port: u16) -> io::Result<SocketAddr> {
+    let with_port = if target.contains(':') {
+        target.to_owned()
+    } else {
+        format!("{}:{}", target, default_port)
+    };
+    with_port
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "target didn't resolve"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scraen::ColorFormat;
+    use std::time::Duration;
+
+    fn listener() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let target = socket.local_addr().unwrap().to_string();
+        (socket, target)
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0; 1024];
+        let len = socket.recv(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    // 200 rgb pixels, 170 fit in a universe
+    fn panel(address: u16) -> PanelFrame {
+        PanelFrame {
+            address,
+            wh: (20, 10),
+            format: ColorFormat::Rgb888,
+            data: (0..600).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn artnet_splits_on_pixel_boundaries() {
+        let (socket, target) = listener();
+        let mut artnet = ArtNet::new(&target).unwrap();
+        let panel = panel(0x0123);
+        artnet.send_panel(&panel);
+
+        for (i, chunk) in panel.data.chunks(510).enumerate() {
+            let packet = receive(&socket);
+            assert_eq!(&packet[..8], b"Art-Net\0");
+            assert_eq!(u16::from_le_bytes([packet[8], packet[9]]), ARTNET_OP_DMX);
+            let universe = 0x0123 + i as u16;
+            assert_eq!(packet[14], (universe & 0xFF) as u8);
+            assert_eq!(packet[15], (universe >> 8) as u8);
+            let len = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+            assert_eq!(len, chunk.len());
+            assert_eq!(&packet[18..], chunk);
+        }
+    }
+
+    #[test]
+    fn sacn_layer_lengths_and_universe() {
+        let (socket, target) = listener();
+        let mut sacn = Sacn::new(&target).unwrap();
+        let panel = panel(7);
+        sacn.send_panel(&panel);
+
+        for (i, chunk) in panel.data.chunks(510).enumerate() {
+            let packet = receive(&socket);
+            assert_eq!(packet.len(), 126 + chunk.len());
+            let be16 = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]);
+            assert_eq!(be16(16), 0x7000 | (packet.len() - 16) as u16);
+            assert_eq!(be16(38), 0x7000 | (packet.len() - 38) as u16);
+            assert_eq!(be16(115), 0x7000 | (packet.len() - 115) as u16);
+            assert_eq!(be16(113), 7 + i as u16);
+            assert_eq!(be16(123) as usize, chunk.len() + 1);
+            assert_eq!(&packet[126..], chunk);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::connection::protocol::Packet;
+use crate::connection::serial::{Port, SerialSettings};
+use crate::connection::{Connection, Encoding, PanelHealth};
+use crate::network::{ArtNet, Sacn};
+use crate::recording::Recorder;
+use crate::scraen::ColorFormat;
+
+// Where a panel's pixels go. `device` is shared by every panel on the same
+// controller, `address` picks the panel on it: the bus address for serial,
+// the first universe for the network protocols.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OutputDim {
+    pub device: Device,
+    pub address: u16,
+}
+
+// in a config file a table with a `type` of serial, artnet, sacn, null or file
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Device {
+    Serial {
+        port: Port,
+        settings: SerialSettings,
+        #[serde(default)]
+        encoding: Encoding,
+    },
+    // host or host:port, see network.rs for the defaults
+    ArtNet {
+        target: Cow<'static, str>,
+    },
+    Sacn {
+        target: Cow<'static, str>,
+    },
+    // throws everything away, for running without hardware
+    Null,
+    // the exact bytes a serial controller would receive, appended to a file
+    File {
+        path: Cow<'static, str>,
+    },
+}
+
+// one panel's worth of packed pixels, as produced by Scraen::serial_packet
+pub struct PanelFrame {
+    pub address: u16,
+    pub wh: (u8, u8),
+    pub format: ColorFormat,
+    pub data: Vec<u8>,
+}
+
+pub trait OutputSink: Send {
+    // keeps links alive, called once per update before any frame is sent
+    fn update(&mut self) {}
+
+    fn begin_frame(&mut self) {}
+
+    fn send_panel(&mut self, panel: &PanelFrame);
+
+    // every panel of the frame has been sent and can be shown
+    fn end_frame(&mut self);
+
+    fn health(&self, _address: u16) -> Option<&PanelHealth> {
+        None
+    }
+}
+
+// one sink per device, shared by every panel routed to it
+pub struct Outputs {
+    sinks: Vec<(Device, Box<dyn OutputSink>)>,
+    recorder: Option<Recorder>,
+}
+
+impl Outputs {
+    pub fn new<'a>(outputs: impl IntoIterator<Item = &'a OutputDim>) -> Outputs {
+        let mut sinks: Vec<(Device, Box<dyn OutputSink>)> = Vec::new();
+        for output in outputs {
+            if let Some((existing, _)) = sinks.iter().find(|(d, _)| same_device(d, &output.device))
+            {
+                if *existing != output.device {
+                    println!(
+                        "{:?} and {:?} share a port, keeping the first",
+                        existing, output.device
+                    );
+                }
+                continue;
+            }
+            match open_sink(&output.device) {
+                Ok(sink) => sinks.push((output.device.clone(), sink)),
+                Err(err) => println!("couldn't open output {:?}: {}", output.device, err),
+            }
+        }
+        Outputs {
+            sinks,
+            recorder: None,
+        }
+    }
+
+    // every panel frame sent from now on is also written to the recorder
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn update(&mut self) {
+        for (_, sink) in &mut self.sinks {
+            sink.update();
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        for (_, sink) in &mut self.sinks {
+            sink.begin_frame();
+        }
+    }
+
+    pub fn send_panel(&mut self, output: &OutputDim, panel: &PanelFrame) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_panel(panel);
+        }
+        if let Some(sink) = self.get_mut(&output.device) {
+            sink.send_panel(panel);
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        for (_, sink) in &mut self.sinks {
+            sink.end_frame();
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_present();
+        }
+    }
+
+    pub fn health(&self, output: &OutputDim) -> Option<&PanelHealth> {
+        self.sinks
+            .iter()
+            .find(|(d, _)| same_device(d, &output.device))?
+            .1
+            .health(output.address)
+    }
+
+    fn get_mut(&mut self, device: &Device) -> Option<&mut Box<dyn OutputSink>> {
+        self.sinks
+            .iter_mut()
+            .find(|(d, _)| same_device(d, device))
+            .map(|(_, sink)| sink)
+    }
+}
+
+// panels on the same serial port share it even if their settings disagree
+fn same_device(a: &Device, b: &Device) -> bool {
+    match (a, b) {
+        (Device::Serial { port: a, .. }, Device::Serial { port: b, .. }) => a == b,
+        _ => a == b,
+    }
+}
+
+fn open_sink(device: &Device) -> io::Result<Box<dyn OutputSink>> {
+    let sink: Box<dyn OutputSink> = match device {
+        Device::Serial {
+            port,
+            settings,
+            encoding,
+        } => Box::new(Connection::new(port.clone(), *settings, true).with_encoding(*encoding)),
+        Device::ArtNet { target } => Box::new(ArtNet::new(target)?),
+        Device::Sacn { target } => Box::new(Sacn::new(target)?),
+        Device::Null => Box::new(NullSink),
+        Device::File { path } => Box::new(FileSink::new(path)?),
+    };
+    Ok(sink)
+}
+
+pub struct NullSink;
+
+impl OutputSink for NullSink {
+    fn send_panel(&mut self, _panel: &PanelFrame) {}
+
+    fn end_frame(&mut self) {}
+}
+
+pub struct FileSink {
+    path: String,
+    file: BufWriter<File>,
+    seq: u16,
+}
+
+impl FileSink {
+    pub fn new(path: &str) -> io::Result<FileSink> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(FileSink {
+            path: path.to_owned(),
+            file: BufWriter::new(file),
+            seq: 0,
+        })
+    }
+
+    fn write_packet(&mut self, packet: Packet) {
+        self.seq = self.seq.wrapping_add(1);
+        if let Err(err) = self.file.write_all(&packet.encode()) {
+            println!("couldn't write to {}: {}", self.path, err);
+        }
+    }
+}
+
+impl OutputSink for FileSink {
+    fn send_panel(&mut self, panel: &PanelFrame) {
+        let address = match u8::try_from(panel.address) {
+            Ok(address) => address,
+            Err(_) => {
+                println!(
+                    "panel address {} is too big for {}",
+                    panel.address, self.path
+                );
+                return;
+            }
+        };
+        let packet = Packet::panel(
+            address,
+            panel.wh,
+            panel.format.to_byte(),
+            self.seq,
+            panel.data.clone(),
+        );
+        self.write_packet(packet);
+    }
+
+    fn end_frame(&mut self) {
+        self.write_packet(Packet::present(self.seq));
+        if let Err(err) = self.file.flush() {
+            println!("couldn't write to {}: {}", self.path, err);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::connection::serial::{Port, SerialSettings};
+use crate::connection::Connection;
+use crate::output::{OutputSink, PanelFrame};
+use crate::scraen::ColorFormat;
+
+pub use eyes::recording::format;
+use format::{Entry, LogReader, LogWriter, Record};
+
+// how long replay waits for the serial port before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Taps every panel frame on its way to the outputs so a glitch seen on site
+// can be replayed on the bench
+pub struct Recorder {
+    path: String,
+    writer: LogWriter,
+    start: Instant,
+}
+
+impl Recorder {
+    // `{time}` in the path is replaced with the local start time
+    pub fn create(path: &str) -> Result<Recorder> {
+        let path = path.replace(
+            "{time}",
+            &chrono::Local::now().format("%Y%m%d-%H%M%S").to_string(),
+        );
+        if let Some(dir) = std::path::Path::new(&path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let writer =
+            LogWriter::create(&path).with_context(|| format!("creating recording {}", path))?;
+        println!("recording panel output to {}", path);
+
+        Ok(Recorder {
+            path,
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record_panel(&mut self, panel: &PanelFrame) {
+        self.write(Entry::Panel {
+            address: panel.address,
+            wh: panel.wh,
+            format: panel.format.to_byte(),
+            data: panel.data.clone(),
+        });
+    }
+
+    pub fn record_present(&mut self) {
+        self.write(Entry::Present);
+        if let Err(err) = self.writer.flush() {
+            println!("couldn't write to {}: {}", self.path, err);
+        }
+    }
+
+    fn write(&mut self, entry: Entry) {
+        let record = Record {
+            time_us: self.start.elapsed().as_micros() as u64,
+            entry,
+        };
+        if let Err(err) = self.writer.write(&record) {
+            println!("couldn't write to {}: {}", self.path, err);
+        }
+    }
+}
+
+// pushes a recording out through a serial port with the original timing
+pub fn replay(recording: &str, port: Port, settings: SerialSettings) -> Result<()> {
+    let mut connection = Connection::new(port.clone(), settings, true);
+    let waiting = Instant::now();
+    while !connection.is_connected() {
+        if waiting.elapsed() > CONNECT_TIMEOUT {
+            return Err(anyhow!("{} didn't connect", port));
+        }
+        connection.update_link();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    replay_into(recording, &mut connection)
+}
+
+// time zero is when the recording started, not its first frame, so a gap
+// before the first frame is kept along with the rest of the pacing
+pub fn replay_into(recording: &str, sink: &mut dyn OutputSink) -> Result<()> {
+    let reader =
+        LogReader::open(recording).with_context(|| format!("opening recording {}", recording))?;
+
+    let start = Instant::now();
+    let mut frames = 0;
+    let mut in_frame = false;
+    for record in reader {
+        let record = record?;
+        let due = Duration::from_micros(record.time_us);
+        let now = start.elapsed();
+        if due > now {
+            thread::sleep(due - now);
+        }
+
+        sink.update();
+        match record.entry {
+            Entry::Panel {
+                address,
+                wh,
+                format,
+                data,
+            } => {
+                if !in_frame {
+                    sink.begin_frame();
+                    in_frame = true;
+                }
+                sink.send_panel(&PanelFrame {
+                    address,
+                    wh,
+                    format: ColorFormat::from_byte(format)
+                        .ok_or_else(|| anyhow!("unknown colour format {}", format))?,
+                    data,
+                });
+            }
+            Entry::Present => {
+                sink.end_frame();
+                in_frame = false;
+                frames += 1;
+            }
+        }
+    }
+    println!("replayed {} frames from {}", frames, recording);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // when each panel and present went out, since replay started
+    struct Timed {
+        start: Instant,
+        sent: Vec<Duration>,
+    }
+
+    impl OutputSink for Timed {
+        fn send_panel(&mut self, _panel: &PanelFrame) {
+            self.sent.push(self.start.elapsed());
+        }
+
+        fn end_frame(&mut self) {
+            self.sent.push(self.start.elapsed());
+        }
+    }
+
+    #[test]
+    fn replay_keeps_the_gap_before_the_first_frame() {
+        let path = std::env::temp_dir().join(format!("eyes-{}-replay.eyrec", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut writer = LogWriter::create(path).unwrap();
+        for (time_us, entry) in [
+            (
+                50_000,
+                Entry::Panel {
+                    address: 0,
+                    wh: (1, 1),
+                    format: ColorFormat::Mono.to_byte(),
+                    data: vec![255],
+                },
+            ),
+            (80_000, Entry::Present),
+        ] {
+            writer.write(&Record { time_us, entry }).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut sink = Timed {
+            start: Instant::now(),
+            sent: Vec::new(),
+        };
+        replay_into(path, &mut sink).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(sink.sent.len(), 2);
+        assert!(sink.sent[0] >= Duration::from_millis(50), "{:?}", sink.sent);
+        assert!(sink.sent[1] >= Duration::from_millis(80), "{:?}", sink.sent);
+    }
+}
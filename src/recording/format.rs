@@ -0,0 +1,383 @@
+// On disk format of a panel output recording, shared with the simulator
+// through the lib.
+//
+// binary (.eyrec), little endian:
+//   header  b"EYESREC" then a version byte (1)
+//   record  u64 microseconds since the recording started
+//           u8  kind, 1 = panel, 2 = present (end of frame)
+//   panel records continue with
+//           u16 address, u8 width, u8 height, u8 colour format, u16 length n, n data bytes
+//   a recording cut off partway through a record reads as an error after the
+//   records before it
+//
+// csv (.csv), one record per line after a header line:
+//   time_us,kind,address,width,height,format,data
+//   with data as lowercase hex and the panel columns left empty for presents
+//
+// neither keeps which device a panel went out on, only its address
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 7] = b"EYESREC";
+const VERSION: u8 = 1;
+const CSV_HEADER: &str = "time_us,kind,address,width,height,format,data";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub time_us: u64,
+    pub entry: Entry,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Panel {
+        address: u16,
+        wh: (u8, u8),
+        format: u8,
+        data: Vec<u8>,
+    },
+    Present,
+}
+
+pub enum LogWriter {
+    Binary(BufWriter<File>),
+    Csv(BufWriter<File>),
+}
+
+pub enum LogReader {
+    Binary(BufReader<File>),
+    Csv(io::Lines<BufReader<File>>),
+}
+
+fn is_csv(path: &str) -> bool {
+    path.to_lowercase().ends_with(".csv")
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl LogWriter {
+    pub fn create(path: &str) -> io::Result<LogWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        if is_csv(path) {
+            writeln!(file, "{}", CSV_HEADER)?;
+            Ok(LogWriter::Csv(file))
+        } else {
+            file.write_all(MAGIC)?;
+            file.write_all(&[VERSION])?;
+            Ok(LogWriter::Binary(file))
+        }
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self {
+            LogWriter::Binary(file) => {
+                // checked before anything goes out so a long panel doesn't
+                // leave half a record behind
+                let len = match &record.entry {
+                    Entry::Panel { data, .. } => u16::try_from(data.len()).map_err(|_| {
+                        invalid(format!(
+                            "{} bytes is too long for a panel record, the most is {}",
+                            data.len(),
+                            u16::MAX
+                        ))
+                    })?,
+                    Entry::Present => 0,
+                };
+                file.write_all(&record.time_us.to_le_bytes())?;
+                match &record.entry {
+                    Entry::Panel {
+                        address,
+                        wh,
+                        format,
+                        data,
+                    } => {
+                        file.write_all(&[1])?;
+                        file.write_all(&address.to_le_bytes())?;
+                        file.write_all(&[wh.0, wh.1, *format])?;
+                        file.write_all(&len.to_le_bytes())?;
+                        file.write_all(data)
+                    }
+                    Entry::Present => file.write_all(&[2]),
+                }
+            }
+            LogWriter::Csv(file) => match &record.entry {
+                Entry::Panel {
+                    address,
+                    wh,
+                    format,
+                    data,
+                } => {
+                    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                    writeln!(
+                        file,
+                        "{},panel,{},{},{},{},{}",
+                        record.time_us, address, wh.0, wh.1, format, hex
+                    )
+                }
+                Entry::Present => writeln!(file, "{},present,,,,,", record.time_us),
+            },
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogWriter::Binary(file) | LogWriter::Csv(file) => file.flush(),
+        }
+    }
+}
+
+impl LogReader {
+    pub fn open(path: &str) -> io::Result<LogReader> {
+        let mut file = BufReader::new(File::open(path)?);
+        if is_csv(path) {
+            let mut lines = file.lines();
+            match lines.next() {
+                Some(Ok(header)) if header.trim() == CSV_HEADER => Ok(LogReader::Csv(lines)),
+                _ => Err(invalid(format!("{} is missing the csv header", path))),
+            }
+        } else {
+            let mut header = [0u8; 8];
+            file.read_exact(&mut header)?;
+            if &header[..7] != MAGIC {
+                return Err(invalid(format!("{} is not a recording", path)));
+            }
+            if header[7] != VERSION {
+                return Err(invalid(format!(
+                    "unsupported recording version {}",
+                    header[7]
+                )));
+            }
+            Ok(LogReader::Binary(file))
+        }
+    }
+
+    fn read_binary(file: &mut BufReader<File>) -> io::Result<Option<Record>> {
+        if file.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        LogReader::read_record(file).map(Some).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                invalid("recording ends partway through a record".to_string())
+            } else {
+                err
+            }
+        })
+    }
+
+    fn read_record(file: &mut BufReader<File>) -> io::Result<Record> {
+        let mut time = [0u8; 8];
+        file.read_exact(&mut time)?;
+        let time_us = u64::from_le_bytes(time);
+
+        let mut kind = [0u8; 1];
+        file.read_exact(&mut kind)?;
+        let entry = match kind[0] {
+            1 => {
+                let mut head = [0u8; 7];
+                file.read_exact(&mut head)?;
+                let len = u16::from_le_bytes([head[5], head[6]]) as usize;
+                let mut data = vec![0u8; len];
+                file.read_exact(&mut data)?;
+                Entry::Panel {
+                    address: u16::from_le_bytes([head[0], head[1]]),
+                    wh: (head[2], head[3]),
+                    format: head[4],
+                    data,
+                }
+            }
+            2 => Entry::Present,
+            k => return Err(invalid(format!("unknown record kind {}", k))),
+        };
+        Ok(Record { time_us, entry })
+    }
+
+    fn parse_csv(line: &str) -> io::Result<Record> {
+        let bad = || invalid(format!("bad recording line: {}", line));
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        if fields.len() != 7 {
+            return Err(bad());
+        }
+        let time_us = fields[0].parse().map_err(|_| bad())?;
+
+        let entry = match fields[1] {
+            "present" => Entry::Present,
+            "panel" => {
+                let hex = fields[6];
+                if !hex.len().is_multiple_of(2) {
+                    return Err(bad());
+                }
+                let data = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| bad())?;
+                Entry::Panel {
+                    address: fields[2].parse().map_err(|_| bad())?,
+                    wh: (
+                        fields[3].parse().map_err(|_| bad())?,
+                        fields[4].parse().map_err(|_| bad())?,
+                    ),
+                    format: fields[5].parse().map_err(|_| bad())?,
+                    data,
+                }
+            }
+            _ => return Err(bad()),
+        };
+        Ok(Record { time_us, entry })
+    }
+}
+
+impl Iterator for LogReader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        match self {
+            LogReader::Binary(file) => LogReader::read_binary(file).transpose(),
+            LogReader::Csv(lines) => loop {
+                match lines.next()? {
+                    Ok(line) if line.trim().is_empty() => continue,
+                    Ok(line) => return Some(LogReader::parse_csv(&line)),
+                    Err(err) => return Some(Err(err)),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                time_us: 0,
+                entry: Entry::Panel {
+                    address: 3,
+                    wh: (2, 2),
+                    format: 1,
+                    data: vec![0, 1, 254, 255],
+                },
+            },
+            Record {
+                time_us: 1_500,
+                entry: Entry::Panel {
+                    address: 300,
+                    wh: (16, 16),
+                    format: 2,
+                    data: vec![],
+                },
+            },
+            Record {
+                time_us: 16_667,
+                entry: Entry::Present,
+            },
+        ]
+    }
+
+    // somewhere of its own in the temp dir, gone when the test is done
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            let path = std::env::temp_dir().join(format!("eyes-{}-{}", std::process::id(), name));
+            TempFile(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn write(path: &str, records: &[Record]) {
+        let mut writer = LogWriter::create(path).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    fn read(path: &str) -> Vec<io::Result<Record>> {
+        LogReader::open(path).unwrap().collect()
+    }
+
+    // drops the last `n` bytes of the file
+    fn truncate(path: &str, n: u64) {
+        let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - n).unwrap();
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let file = TempFile::new("round-trip.eyrec");
+        write(&file.0, &records());
+        let read: Vec<Record> = read(&file.0).into_iter().map(Result::unwrap).collect();
+        assert_eq!(read, records());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let file = TempFile::new("round-trip.csv");
+        write(&file.0, &records());
+        let read: Vec<Record> = read(&file.0).into_iter().map(Result::unwrap).collect();
+        assert_eq!(read, records());
+    }
+
+    #[test]
+    fn binary_truncated_record() {
+        let file = TempFile::new("truncated.eyrec");
+        // short of the kind byte, then partway through the time
+        for n in [1, 5] {
+            write(&file.0, &records());
+            truncate(&file.0, n);
+            let read = read(&file.0);
+            assert_eq!(read.len(), 3);
+            assert_eq!(read[0].as_ref().unwrap(), &records()[0]);
+            assert_eq!(read[1].as_ref().unwrap(), &records()[1]);
+            assert_eq!(
+                read[2].as_ref().unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+    }
+
+    #[test]
+    fn csv_truncated_record() {
+        let file = TempFile::new("truncated.csv");
+        write(&file.0, &records());
+        truncate(&file.0, 5);
+        let read = read(&file.0);
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[0].as_ref().unwrap(), &records()[0]);
+        assert_eq!(read[1].as_ref().unwrap(), &records()[1]);
+        assert!(read[2].is_err());
+    }
+
+    #[test]
+    fn binary_rejects_long_panel() {
+        let file = TempFile::new("long.eyrec");
+        let mut writer = LogWriter::create(&file.0).unwrap();
+        let long = Record {
+            time_us: 0,
+            entry: Entry::Panel {
+                address: 0,
+                wh: (255, 255),
+                format: 0,
+                data: vec![0; u16::MAX as usize + 1],
+            },
+        };
+        assert!(writer.write(&long).is_err());
+        writer.write(&records()[2]).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let read: Vec<Record> = read(&file.0).into_iter().map(Result::unwrap).collect();
+        assert_eq!(read, vec![records()[2].clone()]);
+    }
+}
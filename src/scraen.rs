@@ -1,6 +1,10 @@
 use std::iter::Flatten;
 
-use crate::{Connection, Model, ScraenDim, SCRAEN_SCALE};
+use anyhow::{Context, Result};
+
+use crate::output::PanelFrame;
+use crate::vision::Scene;
+use crate::{OutputDim, ScraenDim};
 use futures::io::Close;
 use image::{imageops::FilterType, math, DynamicImage, GenericImageView, Pixel};
 use nannou::{
@@ -21,8 +25,31 @@ use randomwalk::translators::{ExponentialTranslator, LogNormalTranslator, Unifor
 
 pub mod fbo;
 use fbo::Fbo;
+pub use eyes::scraen::color;
+pub use color::ColorFormat;
+pub use eyes::scraen::mapping;
+pub use mapping::PixelMapping;
+pub mod tone;
+use tone::Tone;
+pub use eyes::scraen::orientation;
+pub use orientation::Orientation;
+pub mod pattern;
+pub use pattern::TestPattern;
+pub mod style;
+pub mod pupil;
+pub mod expression;
+pub mod blink;
+pub use blink::BlinkDim;
+use blink::{Blink, BlinkKind};
+use expression::{Expression, ExpressionState};
+pub use pupil::PupilDim;
+use pupil::Pupil;
+pub use style::StyleDim;
+use style::{build_style, EyeState, EyeStyle};
 
 const UPSCALE_VAL: u32 = 3;
+// window size of one panel pixel, before the window scale
+pub const PIXEL_SIZE: f32 = 10.0;
 
 pub struct Scraen {
     pub fbo: Fbo,
@@ -33,6 +60,8 @@ pub struct Scraen {
     scraen_texture: wgpu::Texture,
 
     blink: Blink,
+    pupil: Pupil,
+    expression: ExpressionState,
 
     eye_open_percent: f32,
     eye_r: f32,
@@ -40,42 +69,48 @@ pub struct Scraen {
     eye_rt: Point2,
 
     fbo_rect: Rect,
+    // where the panel sits in the window
     draw_rect: Rect,
+    // its size in the window in its own frame, before the orientation
+    panel_size: Vec2,
     target_pos: Vec2,
 
     webcam_rect: Rect,
     target_acc: Vec2,
     target_vel: Vec2,
 
-    rotate: bool,
+    orientation: Orientation,
+    style: Box<dyn EyeStyle>,
+    output: OutputDim,
+    // wire slot -> pixel index, see PixelMapping
+    mapping: Vec<Option<usize>>,
+    format: ColorFormat,
+    tone: Tone,
+    // shown in place of the eye while a test pattern is on
+    pattern: Option<DynamicImage>,
 }
 
 impl Scraen {
-    pub fn new(app: &App, params: ScraenDim, webcam_rect: Rect) -> Scraen {
+    pub fn new(app: &App, params: ScraenDim, webcam_rect: Rect, scale: f32) -> Result<Scraen> {
         let scraen_resolution = (params.rez, params.rez);
         let fbo_resolution = (params.rez * UPSCALE_VAL, params.rez * UPSCALE_VAL);
 
         let fbo_rect =
             Rect::from_x_y_w_h(0.0, 0.0, fbo_resolution.0 as f32, fbo_resolution.1 as f32);
 
-        let draw_rect = Rect::from_x_y_w_h(
-            params.xy.0,
-            params.xy.1,
-            params.wh.0 * SCRAEN_SCALE,
-            params.wh.1 * -SCRAEN_SCALE,
-        );
+        let draw_rect = draw_rect(params.xy, params.wh, scale, params.orientation);
         // let fbo_resolution = params.(w * 20, h * 20);
 
         let frame_buffer = Fbo::new(app, (fbo_resolution.0, fbo_resolution.1));
         let img = &DynamicImage::new_rgb8(params.rez, params.rez);
         let texture = wgpu::Texture::from_image(app, img);
-        let window_transform = Affine2::from_scale_angle_translation(
-            draw_rect.wh() / fbo_rect.wh(),
-            0.0,
-            draw_rect.xy(),
-        );
+        // a table file is read again here, it can have changed since validation
+        let mapping = params.mapping.table(scraen_resolution).context("mapping")?;
+
+        let window_transform =
+            window_transform(params.xy, params.wh, scale, fbo_rect, params.orientation);
 
-        Scraen {
+        Ok(Scraen {
             fbo: frame_buffer,
             fbo_resolution,
 
@@ -88,6 +123,7 @@ impl Scraen {
 
             fbo_rect,
             draw_rect,
+            panel_size: panel_size(params.wh, scale),
             webcam_rect,
 
             eye_r: fbo_rect.h() / 4.0,
@@ -97,13 +133,22 @@ impl Scraen {
             target_vel: Vec2::splat(0.0),
             target_acc: Vec2::splat(0.0),
 
-            blink: Blink::new(0.2, 0.1, 0.1, 400),
-            rotate: params.rotate,
-        }
+            blink: Blink::new(params.blink),
+            pupil: Pupil::new(params.pupil),
+            expression: ExpressionState::new(),
+            orientation: params.orientation,
+            style: build_style(&params.style),
+            output: params.output,
+            mapping,
+            format: params.format,
+            tone: Tone::new(params.tone),
+            pattern: None,
+        })
     }
 
-    pub fn update(&mut self, app: &App, target: Point2, time: f64) {
+    pub fn update(&mut self, target: Point2, scene: &Scene, time: f64) {
         self.blink.update(time);
+        self.pupil.update(scene, time);
 
         //smooth target eye motion with accselatation
         let new_target_vel = self.target_pos - target;
@@ -120,216 +165,146 @@ impl Scraen {
         self.eye_rt = vec2(radius, theta);
         self.eye_xy = vec2(radius * theta.cos(), radius * theta.sin());
     }
-    pub fn draw_eye2(&self) {
-        let draw = &self.fbo.draw();
-        draw.background().color(BLACK);
-        let rect_height = self.eye_r * self.blink.val;
-
-        let rect_wh = vec2(self.eye_r * 2.0, rect_height);
-        let rect_xy = vec2(0.0, self.eye_r - (rect_height / 2.0));
-
-        draw.text(std::str::from_utf8(&[random_ascii() as u8]).unwrap())
-            .xy(self.eye_xy)
-            .font_size(24)
-            .color(WHITE);
-
-        draw.rect()
-            .xy(self.eye_xy - rect_xy)
-            .wh(rect_wh)
-            .color(BLACK);
-
-        draw.rect()
-            .xy(self.eye_xy + rect_xy)
-            .wh(rect_wh)
-            .color(BLACK);
+    // started by blink::Blinker
+    pub fn blink(&mut self, kind: BlinkKind, depth: f32, time: f64) {
+        self.blink.start(kind, depth, time);
     }
 
-    pub fn draw_eye1(&self) {
-        // self.eye_xy = vec2(radius * theta.cos(), radius * theta.sin());
-        let rad = self.eye_rt.x;
-        let angle = self.eye_rt.y;
-        let angle2 = self.eye_rt.y + PI;
-
-        let start = vec2(rad * angle.cos(), rad * angle.sin());
-
-        let end = vec2(rad * angle2.cos(), rad * angle2.sin());
-
-        let draw = &self.fbo.draw();
-        draw.background().color(BLACK);
-        let rect_height = self.eye_r * self.blink.val;
-
-        let rect_wh = vec2(self.eye_r * 2.0, rect_height);
-        let rect_xy = vec2(0.0, self.eye_r - (rect_height / 2.0));
-
-        draw.arrow().start(start).end(end).weight(2.0).color(WHITE);
-
-        draw.rect()
-            .xy(self.eye_xy - rect_xy)
-            .wh(rect_wh)
-            .color(BLACK);
-
-        draw.rect()
-            .xy(self.eye_xy + rect_xy)
-            .wh(rect_wh)
-            .color(BLACK);
+    pub fn set_expression(&mut self, expression: Expression, time: f64, duration: f64) {
+        self.expression.set(expression, time, duration);
     }
 
-    pub fn draw_eye(&self) {
-        let draw = &self.fbo.draw();
-        draw.background().color(BLACK);
-        let rect_height = self.eye_r * self.blink.val;
-
-        let rect_wh = vec2(self.eye_r * 2.0, rect_height);
-        let rect_xy = vec2(0.0, self.eye_r - (rect_height / 2.0));
-
-        draw.ellipse()
-            .xy(self.eye_xy)
-            .radius(self.eye_r)
-            .color(WHITE);
+    // `index` is the panel's place in the config, for TestPattern::Index
+    pub fn set_pattern(&mut self, pattern: Option<TestPattern>, index: usize, time: f64) {
+        self.pattern = pattern.map(|p| p.render(self.scraen_resolution, index, time));
+    }
 
-        draw.rect()
-            .xy(self.eye_xy - rect_xy)
-            .wh(rect_wh)
-            .color(BLACK);
+    pub fn showing_pattern(&self) -> bool {
+        self.pattern.is_some()
+    }
 
-        draw.rect()
-            .xy(self.eye_xy + rect_xy)
-            .wh(rect_wh)
-            .color(BLACK);
+    pub fn draw_eye(&mut self, time: f64) {
+        let eye = EyeState {
+            xy: self.eye_xy,
+            rt: self.eye_rt,
+            r: self.eye_r,
+            size: self.fbo_rect.wh(),
+            pixel: UPSCALE_VAL as f32,
+            blink: self.blink.val,
+            pupil: self.pupil.val,
+            lids: self.expression.lids(time),
+            time,
+        };
+        self.style.draw(self.fbo.draw(), &eye);
     }
 
     pub fn render_texture(&mut self, app: &App) {
         self.fbo.render(app);
         self.fbo.snapshot_texture(app);
 
-        if let Ok(image) = self.fbo.image_buffer.try_lock() {
-            self.scraen_texture = wgpu::Texture::from_image(
-                app,
-                &image.resize_exact(
-                    self.scraen_resolution.0,
-                    self.scraen_resolution.1,
-                    FilterType::Gaussian,
-                ),
-            );
+        if let Some(image) = self.panel_image(FilterType::Gaussian) {
+            // the preview shows what the panel is sent, turned back in draw_to_frame
+            self.scraen_texture =
+                wgpu::Texture::from_image(app, &self.orientation.to_panel(image));
+        }
+    }
+
+    // the upright image at the panel's resolution, the test pattern if one is on
+    fn panel_image(&self, filter: FilterType) -> Option<DynamicImage> {
+        if let Some(pattern) = &self.pattern {
+            return Some(pattern.clone());
         }
+        let image = self.fbo.image_buffer.try_lock().ok()?;
+        Some(image.resize_exact(self.scraen_resolution.0, self.scraen_resolution.1, filter))
     }
 
     pub fn draw_to_frame(&self, draw: &Draw) {
         let t = self.window_transform;
 
-        draw.texture(&self.scraen_texture)
-            .xy(t.transform_point2(self.fbo_rect.xy()))
-            .wh(t.transform_vector2(self.fbo_rect.wh()));
+        draw.xy(t.transform_point2(self.fbo_rect.xy()))
+            .rotate(self.orientation.angle())
+            .scale_axes(self.orientation.mirror_signs().extend(1.0))
+            .texture(&self.scraen_texture)
+            .wh(self.panel_size);
 
+        let eye = self.orientation.world_to_panel(self.eye_xy);
         draw.line()
             .start(self.draw_rect.xy())
-            .end(self.window_transform.transform_point2(self.eye_xy))
+            .end(t.transform_point2(eye))
             .color(GREY)
             .color(GREY);
     }
 
-    pub fn serial_packet(&self) -> Option<Vec<u8>> {
-        if let Ok(image) = self.fbo.image_buffer.try_lock() {
-            let mut small_img = image.resize_exact(
-                self.scraen_resolution.0,
-                self.scraen_resolution.1,
-                FilterType::Triangle,
-            );
-            if self.rotate {
-                small_img = small_img.rotate270();
-            } else {
-                small_img = small_img.rotate90();
-            }
+    pub fn output(&self) -> &OutputDim {
+        &self.output
+    }
+
+    pub fn draw_rect(&self) -> Rect {
+        self.draw_rect
+    }
 
-            let mut itt = small_img
-                .clone()
+    // moves the panel in the window without rebuilding it, for the layout editor
+    pub fn set_layout(&mut self, xy: (f32, f32), wh: (f32, f32), scale: f32) {
+        self.draw_rect = draw_rect(xy, wh, scale, self.orientation);
+        self.panel_size = panel_size(wh, scale);
+        self.window_transform = window_transform(xy, wh, scale, self.fbo_rect, self.orientation);
+    }
+
+    pub fn serial_packet(&mut self) -> Option<PanelFrame> {
+        if let Some(small_img) = self.panel_image(FilterType::Triangle) {
+            // the wiring is a quarter turn from an upright panel
+            let small_img = self.orientation.to_panel(small_img).rotate90();
+
+            let mut pixels: Vec<[u8; 3]> = small_img
                 .as_rgba8()?
-                .enumerate_rows()
-                .flat_map(|(i, row)| {
-                    let mut mapped_row: Vec<u8> = row
-                        .map(|(x, y, pix)| clamp(pix.to_luma().channels()[0], 0u8, 200u8))
-                        .collect();
-                    if i % 2 == 0 {
-                        mapped_row.reverse();
-                    }
-
-                    mapped_row.push(0);
-                    mapped_row
-                })
-                .collect::<Vec<u8>>();
-            itt.pop();
-            Some(itt)
+                .pixels()
+                .map(|pix| pix.to_rgb().0)
+                .collect();
+            self.tone.apply(&mut pixels, small_img.width());
+
+            let mut buf = Vec::with_capacity(self.mapping.len() * self.format.bytes_per_pixel());
+            for slot in &self.mapping {
+                match slot {
+                    Some(i) => self.format.pack(pixels[*i], &mut buf),
+                    None => self.format.pack_blank(&mut buf),
+                }
+            }
+            Some(PanelFrame {
+                address: self.output.address,
+                // the grid the mapping walks, after rotation
+                wh: (small_img.width() as u8, small_img.height() as u8),
+                format: self.format,
+                data: buf,
+            })
         } else {
             None
         }
     }
 }
 
-type StartTime = f64;
-enum State {
-    Closing(StartTime),
-    Closed(StartTime),
-    Opening(StartTime),
-    Dorment,
-}
-struct Blink {
-    state: State,
-    shutting_time: f64,
-    closed_time: f64,
-    opening_time: f64,
-    chance: u32,
-    val: f32,
+// size in the window in the panel's own frame, the negative height flips it vertically
+fn panel_size(wh: (f32, f32), scale: f32) -> Vec2 {
+    vec2(wh.0 * PIXEL_SIZE * scale, wh.1 * -PIXEL_SIZE * scale)
 }
 
-impl Blink {
-    fn new(shutting_time: f64, closed_time: f64, opening_time: f64, chance: u32) -> Blink {
-        Blink {
-            state: State::Dorment,
-            shutting_time,
-            closed_time,
-            opening_time,
-            chance,
-            val: 0.0,
-        }
-    }
+// the part of the window the panel covers once it is turned
+fn draw_rect(xy: (f32, f32), wh: (f32, f32), scale: f32, orientation: Orientation) -> Rect {
+    Rect::from_xy_wh(vec2(xy.0, xy.1), panel_size(orientation.footprint(wh), scale))
+}
 
-    fn update(&mut self, time: f64) {
-        self.val = match self.state {
-            State::Closing(start_time) => {
-                let t = time - start_time;
-                if t < self.shutting_time {
-                    ease::sine::ease_in(t, 0.0, 1.0, self.shutting_time) as f32
-                } else {
-                    self.state = State::Closed(time);
-                    1.0
-                }
-            }
-            State::Closed(start_time) => {
-                let t = time - start_time;
-                if t < self.closed_time {
-                    1.0
-                } else {
-                    self.state = State::Opening(time);
-                    1.0
-                }
-            }
-            State::Opening(start_time) => {
-                let t = time - start_time;
-                if t < self.opening_time {
-                    (1.0 - ease::sine::ease_out(t, 0.0, 1.0, self.opening_time) as f32)
-                } else {
-                    self.state = State::Dorment;
-                    0.0
-                }
-            }
-            State::Dorment => {
-                if random_range(0, self.chance) < 1 {
-                    self.state = State::Closing(time)
-                };
-                0.0
-            }
-        }
-    }
+// fbo pixels in the panel's frame to the window
+fn window_transform(
+    xy: (f32, f32),
+    wh: (f32, f32),
+    scale: f32,
+    fbo_rect: Rect,
+    orientation: Orientation,
+) -> Affine2 {
+    Affine2::from_scale_angle_translation(
+        panel_size(wh, scale) / fbo_rect.wh() * orientation.mirror_signs(),
+        orientation.angle(),
+        vec2(xy.0, xy.1),
+    )
 }
 
 struct EaseBlink {
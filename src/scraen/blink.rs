@@ -0,0 +1,357 @@
+use nannou::ease;
+use nannou::rand::{Rng, SeedableRng};
+use rand_hc::Hc128Rng;
+use serde::{Deserialize, Serialize};
+
+use super::Scraen;
+
+// seconds for each phase of one panel's blink
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BlinkDim {
+    pub shutting_time: f64,
+    pub closed_time: f64,
+    pub opening_time: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlinkSync {
+    // every panel at the same moment
+    Together,
+    // a wave across the wall from left to right
+    Staggered,
+    // every panel on its own schedule
+    Independent,
+}
+
+// When the wall blinks and how. The gaps between blinks are random with
+// `interval_sec` as the average, so the rate is the same at any frame rate.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BlinkerDim {
+    pub sync: BlinkSync,
+    pub interval_sec: f64,
+    // no two blinks closer than this
+    pub min_interval_sec: f64,
+    // delay between neighbouring panels when staggered
+    pub stagger_sec: f64,
+    // chances out of 1.0 for each kind of blink, the rest are single blinks
+    pub double_chance: f64,
+    pub half_chance: f64,
+    // one panel blinks alone, when not independent
+    pub wink_chance: f64,
+    // how far a half blink closes
+    pub half_depth: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlinkKind {
+    Single,
+    // shut, open and straight back shut
+    Double,
+    // closes part of the way
+    Half,
+}
+
+type StartTime = f64;
+enum State {
+    Closing(StartTime),
+    Closed(StartTime),
+    Opening(StartTime),
+    Dorment,
+}
+
+// One panel's lids, started by Blinker.
+pub struct Blink {
+    state: State,
+    shutting_time: f64,
+    closed_time: f64,
+    opening_time: f64,
+    // how far this blink closes, 1.0 all the way
+    depth: f32,
+    // blinks left to go after this one
+    repeats: u32,
+    pub val: f32,
+}
+
+impl Blink {
+    pub fn new(dim: BlinkDim) -> Blink {
+        Blink {
+            state: State::Dorment,
+            shutting_time: dim.shutting_time,
+            closed_time: dim.closed_time,
+            opening_time: dim.opening_time,
+            depth: 1.0,
+            repeats: 0,
+            val: 0.0,
+        }
+    }
+
+    // a blink already going is left to finish
+    pub fn start(&mut self, kind: BlinkKind, depth: f32, time: f64) {
+        if !matches!(self.state, State::Dorment) {
+            return;
+        }
+        let (depth, repeats) = match kind {
+            BlinkKind::Single => (1.0, 0),
+            BlinkKind::Double => (1.0, 1),
+            BlinkKind::Half => (depth, 0),
+        };
+        self.depth = depth;
+        self.repeats = repeats;
+        self.state = State::Closing(time);
+    }
+
+    pub fn update(&mut self, time: f64) {
+        let closed = match self.state {
+            State::Closing(start_time) => {
+                let t = time - start_time;
+                if t < self.shutting_time {
+                    ease::sine::ease_in(t, 0.0, 1.0, self.shutting_time) as f32
+                } else {
+                    self.state = State::Closed(time);
+                    1.0
+                }
+            }
+            State::Closed(start_time) => {
+                let t = time - start_time;
+                if t >= self.closed_time {
+                    self.state = State::Opening(time);
+                }
+                1.0
+            }
+            State::Opening(start_time) => {
+                let t = time - start_time;
+                if t < self.opening_time {
+                    1.0 - ease::sine::ease_out(t, 0.0, 1.0, self.opening_time) as f32
+                } else if self.repeats > 0 {
+                    self.repeats -= 1;
+                    self.state = State::Closing(time);
+                    0.0
+                } else {
+                    self.state = State::Dorment;
+                    0.0
+                }
+            }
+            State::Dorment => 0.0,
+        };
+        self.val = closed * self.depth;
+    }
+}
+
+// Schedules the blinks for every Scraen, so panels can blink together or on
+// their own.
+pub struct Blinker {
+    dim: BlinkerDim,
+    // when the wall blinks next, or each panel when independent
+    next: Vec<f64>,
+    // blinks waiting for their start time, staggered ones go out late
+    pending: Vec<(f64, usize, BlinkKind)>,
+    rng: Hc128Rng,
+}
+
+impl Blinker {
+    pub fn new(dim: BlinkerDim) -> Blinker {
+        Blinker::with_rng(dim, Hc128Rng::from_entropy())
+    }
+
+    fn with_rng(dim: BlinkerDim, rng: Hc128Rng) -> Blinker {
+        Blinker {
+            dim,
+            next: Vec::new(),
+            pending: Vec::new(),
+            rng,
+        }
+    }
+
+    pub fn set_dim(&mut self, dim: BlinkerDim) {
+        if dim.sync != self.dim.sync {
+            self.next.clear();
+        }
+        self.dim = dim;
+    }
+
+    pub fn update(&mut self, scraens: &mut [Scraen], time: f64) {
+        let xs: Vec<f32> = scraens.iter().map(|s| s.draw_rect().x()).collect();
+        let depth = self.dim.half_depth;
+        for (panel, kind) in self.due(&xs, time) {
+            // panels can go away when the config is reloaded
+            if let Some(scraen) = scraens.get_mut(panel) {
+                scraen.blink(kind, depth, time);
+            }
+        }
+    }
+
+    // the blinks to start now, xs has every panel's place across the window
+    fn due(&mut self, xs: &[f32], time: f64) -> Vec<(usize, BlinkKind)> {
+        let panels = xs.len();
+        let schedules = match self.dim.sync {
+            BlinkSync::Independent => panels,
+            _ => 1,
+        };
+        if self.next.len() != schedules {
+            self.next = (0..schedules).map(|_| time + self.interval()).collect();
+        }
+
+        for i in 0..schedules {
+            if time < self.next[i] {
+                continue;
+            }
+            self.next[i] = time + self.interval();
+            let kind = self.kind();
+            match self.dim.sync {
+                BlinkSync::Independent => self.pending.push((time, i, kind)),
+                _ if self.rng.gen::<f64>() < self.dim.wink_chance && panels > 0 => {
+                    let panel = self.rng.gen_range(0..panels);
+                    self.pending.push((time, panel, kind));
+                }
+                BlinkSync::Together => self.pending.extend((0..panels).map(|p| (time, p, kind))),
+                BlinkSync::Staggered => {
+                    let mut order: Vec<usize> = (0..panels).collect();
+                    order.sort_by(|a, b| xs[*a].total_cmp(&xs[*b]));
+                    for (n, p) in order.into_iter().enumerate() {
+                        self.pending
+                            .push((time + n as f64 * self.dim.stagger_sec, p, kind));
+                    }
+                }
+            }
+        }
+
+        let mut due = Vec::new();
+        self.pending.retain(|&(at, panel, kind)| {
+            if at > time {
+                return true;
+            }
+            due.push((panel, kind));
+            false
+        });
+        due
+    }
+
+    // exponential gaps, the way a Poisson process would space them
+    fn interval(&mut self) -> f64 {
+        let mean = (self.dim.interval_sec - self.dim.min_interval_sec).max(0.0);
+        self.dim.min_interval_sec - mean * (1.0 - self.rng.gen::<f64>()).ln()
+    }
+
+    fn kind(&mut self) -> BlinkKind {
+        let roll = self.rng.gen::<f64>();
+        if roll < self.dim.double_chance {
+            BlinkKind::Double
+        } else if roll < self.dim.double_chance + self.dim.half_chance {
+            BlinkKind::Half
+        } else {
+            BlinkKind::Single
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: f64 = 0.01;
+
+    fn blinker(dim: BlinkerDim) -> Blinker {
+        Blinker::with_rng(dim, Hc128Rng::seed_from_u64(7))
+    }
+
+    fn dim(sync: BlinkSync) -> BlinkerDim {
+        BlinkerDim {
+            sync,
+            interval_sec: 2.0,
+            min_interval_sec: 1.0,
+            stagger_sec: 0.1,
+            double_chance: 0.0,
+            half_chance: 0.0,
+            wink_chance: 0.0,
+            half_depth: 0.5,
+        }
+    }
+
+    // every batch of blinks over ten minutes, with when it went out
+    fn run(blinker: &mut Blinker, panels: usize) -> Vec<(f64, Vec<(usize, BlinkKind)>)> {
+        let xs: Vec<f32> = (0..panels).map(|p| p as f32).collect();
+        (0..60_000)
+            .map(|n| n as f64 * STEP)
+            .map(|time| (time, blinker.due(&xs, time)))
+            .filter(|(_, due)| !due.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn intervals_respect_the_minimum() {
+        let mut blinker = blinker(dim(BlinkSync::Together));
+        let times: Vec<f64> = run(&mut blinker, 1).into_iter().map(|(t, _)| t).collect();
+        assert!(times.len() > 200, "only {} blinks", times.len());
+        assert!(times[0] >= 1.0);
+        for pair in times.windows(2) {
+            assert!(pair[1] - pair[0] >= 1.0 - STEP, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn together_and_staggered_reach_every_panel() {
+        let mut together = blinker(dim(BlinkSync::Together));
+        for (_, due) in run(&mut together, 3) {
+            let panels: Vec<usize> = due.iter().map(|(p, _)| *p).collect();
+            assert_eq!(panels, vec![0, 1, 2]);
+        }
+
+        // one at a time, left to right
+        let mut staggered = blinker(dim(BlinkSync::Staggered));
+        let panels: Vec<usize> = run(&mut staggered, 3)
+            .into_iter()
+            .flat_map(|(_, due)| due)
+            .map(|(p, _)| p)
+            .take(6)
+            .collect();
+        assert_eq!(panels, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn wink_hits_one_panel() {
+        let mut blinker = blinker(BlinkerDim {
+            wink_chance: 1.0,
+            ..dim(BlinkSync::Together)
+        });
+        let batches = run(&mut blinker, 4);
+        assert!(!batches.is_empty());
+        for (time, due) in &batches {
+            assert_eq!(due.len(), 1, "at {}", time);
+        }
+        // and not always the same one
+        let first = batches[0].1[0].0;
+        assert!(batches.iter().any(|(_, due)| due[0].0 != first));
+    }
+
+    #[test]
+    fn double_blink_closes_twice() {
+        let mut blinker = blinker(BlinkerDim {
+            double_chance: 1.0,
+            ..dim(BlinkSync::Together)
+        });
+        let (time, due) = run(&mut blinker, 1).remove(0);
+        assert_eq!(due, vec![(0, BlinkKind::Double)]);
+
+        let mut blink = Blink::new(BlinkDim {
+            shutting_time: 0.1,
+            closed_time: 0.05,
+            opening_time: 0.1,
+        });
+        blink.start(BlinkKind::Double, 1.0, time);
+        let mut closes = 0;
+        let mut was_shut = false;
+        for n in 0..200 {
+            blink.update(time + n as f64 * STEP);
+            let shut = blink.val == 1.0;
+            if shut && !was_shut {
+                closes += 1;
+            }
+            was_shut = shut;
+        }
+        assert_eq!(closes, 2);
+        assert_eq!(blink.val, 0.0);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+
+// Byte layout of one pixel on the wire. The wire value goes in the format
+// byte of every panel packet so the controller knows how to unpack the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorFormat {
+    // one luma byte
+    Mono,
+    Rgb888,
+    // WS2812 style strips want green first
+    Grb888,
+    // 5 bits red, 6 green, 5 blue, high byte first
+    Rgb565,
+    // white is pulled out of the common part of r, g and b
+    Rgbw,
+}
+
+impl ColorFormat {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ColorFormat::Mono => 0,
+            ColorFormat::Rgb888 => 1,
+            ColorFormat::Grb888 => 2,
+            ColorFormat::Rgb565 => 3,
+            ColorFormat::Rgbw => 4,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<ColorFormat> {
+        match byte {
+            0 => Some(ColorFormat::Mono),
+            1 => Some(ColorFormat::Rgb888),
+            2 => Some(ColorFormat::Grb888),
+            3 => Some(ColorFormat::Rgb565),
+            4 => Some(ColorFormat::Rgbw),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            ColorFormat::Mono => 1,
+            ColorFormat::Rgb888 | ColorFormat::Grb888 => 3,
+            ColorFormat::Rgb565 => 2,
+            ColorFormat::Rgbw => 4,
+        }
+    }
+
+    pub fn pack(self, [r, g, b]: [u8; 3], out: &mut Vec<u8>) {
+        match self {
+            ColorFormat::Mono => out.push(luma([r, g, b])),
+            ColorFormat::Rgb888 => out.extend_from_slice(&[r, g, b]),
+            ColorFormat::Grb888 => out.extend_from_slice(&[g, r, b]),
+            ColorFormat::Rgb565 => {
+                let v = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+            ColorFormat::Rgbw => {
+                let w = r.min(g).min(b);
+                out.extend_from_slice(&[r - w, g - w, b - w, w]);
+            }
+        }
+    }
+
+    // a blank slot from the pixel mapping
+    pub fn pack_blank(self, out: &mut Vec<u8>) {
+        out.extend((0..self.bytes_per_pixel()).map(|_| 0));
+    }
+
+    // inverse of pack, used to preview what a panel will show
+    pub fn unpack(self, bytes: &[u8]) -> [u8; 3] {
+        match self {
+            ColorFormat::Mono => [bytes[0]; 3],
+            ColorFormat::Rgb888 => [bytes[0], bytes[1], bytes[2]],
+            ColorFormat::Grb888 => [bytes[1], bytes[0], bytes[2]],
+            ColorFormat::Rgb565 => {
+                let v = u16::from_be_bytes([bytes[0], bytes[1]]);
+                [
+                    ((v >> 11) as u8) << 3,
+                    (((v >> 5) & 0x3F) as u8) << 2,
+                    ((v & 0x1F) as u8) << 3,
+                ]
+            }
+            ColorFormat::Rgbw => {
+                let w = bytes[3];
+                [
+                    bytes[0].saturating_add(w),
+                    bytes[1].saturating_add(w),
+                    bytes[2].saturating_add(w),
+                ]
+            }
+        }
+    }
+}
+
+// rec. 709 weights, same as image's to_luma
+fn luma([r, g, b]: [u8; 3]) -> u8 {
+    ((2126 * r as u32 + 7152 * g as u32 + 722 * b as u32) / 10000) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ColorFormat; 5] = [
+        ColorFormat::Mono,
+        ColorFormat::Rgb888,
+        ColorFormat::Grb888,
+        ColorFormat::Rgb565,
+        ColorFormat::Rgbw,
+    ];
+
+    fn packed(format: ColorFormat, pixel: [u8; 3]) -> Vec<u8> {
+        let mut out = Vec::new();
+        format.pack(pixel, &mut out);
+        out
+    }
+
+    // the firmware switches on these, they can't change
+    #[test]
+    fn wire_bytes_are_stable() {
+        let bytes: Vec<u8> = ALL.iter().map(|f| f.to_byte()).collect();
+        assert_eq!(bytes, vec![0, 1, 2, 3, 4]);
+        for format in ALL {
+            assert_eq!(ColorFormat::from_byte(format.to_byte()), Some(format));
+        }
+        assert_eq!(ColorFormat::from_byte(5), None);
+    }
+
+    #[test]
+    fn pack_each_format() {
+        let pixel = [200, 100, 50];
+        assert_eq!(packed(ColorFormat::Mono, pixel), vec![117]);
+        assert_eq!(packed(ColorFormat::Rgb888, pixel), vec![200, 100, 50]);
+        assert_eq!(packed(ColorFormat::Grb888, pixel), vec![100, 200, 50]);
+        // 11001 011001 00110
+        assert_eq!(
+            packed(ColorFormat::Rgb565, pixel),
+            vec![0b1100_1011, 0b0010_0110]
+        );
+        assert_eq!(packed(ColorFormat::Rgbw, pixel), vec![150, 50, 0, 50]);
+    }
+
+    #[test]
+    fn pack_sizes_and_blanks() {
+        for format in ALL {
+            assert_eq!(packed(format, [1, 2, 3]).len(), format.bytes_per_pixel());
+            let mut blank = Vec::new();
+            format.pack_blank(&mut blank);
+            assert_eq!(blank, vec![0; format.bytes_per_pixel()]);
+        }
+    }
+
+    #[test]
+    fn unpack_reverses_pack() {
+        for pixel in [
+            [0, 0, 0],
+            [255, 255, 255],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+        ] {
+            for format in [ColorFormat::Rgb888, ColorFormat::Grb888, ColorFormat::Rgbw] {
+                assert_eq!(format.unpack(&packed(format, pixel)), pixel);
+            }
+        }
+        assert_eq!(
+            ColorFormat::Rgb565.unpack(&packed(ColorFormat::Rgb565, [255, 255, 255])),
+            [248, 252, 248]
+        );
+        assert_eq!(ColorFormat::Mono.unpack(&[117]), [117; 3]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::str::FromStr;
+
+use nannou::ease;
+
+// One eyelid's edge, on top of the blink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lid {
+    // 1.0 is a relaxed eye, 0.0 shut and over 1.0 wide open
+    pub openness: f32,
+    // positive raises the right end, as seen from the front
+    pub tilt: f32,
+    // 1.0 follows the eye's curve, 0.0 is a straight edge
+    pub curve: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lids {
+    pub upper: Lid,
+    pub lower: Lid,
+}
+
+impl Lids {
+    pub const NEUTRAL: Lids = Lids {
+        upper: Lid::RELAXED,
+        lower: Lid::RELAXED,
+    };
+
+    fn lerp(&self, to: &Lids, t: f32) -> Lids {
+        Lids {
+            upper: self.upper.lerp(&to.upper, t),
+            lower: self.lower.lerp(&to.lower, t),
+        }
+    }
+}
+
+impl Lid {
+    pub const RELAXED: Lid = Lid {
+        openness: 1.0,
+        tilt: 0.0,
+        curve: 1.0,
+    };
+
+    const fn new(openness: f32, tilt: f32, curve: f32) -> Lid {
+        Lid {
+            openness,
+            tilt,
+            curve,
+        }
+    }
+
+    fn lerp(&self, to: &Lid, t: f32) -> Lid {
+        Lid {
+            openness: self.openness + (to.openness - self.openness) * t,
+            tilt: self.tilt + (to.tilt - self.tilt) * t,
+            curve: self.curve + (to.curve - self.curve) * t,
+        }
+    }
+}
+
+// Named lid shapes, picked by Behaviour or over OSC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expression {
+    Neutral,
+    Surprised,
+    Sleepy,
+    Angry,
+    Suspicious,
+}
+
+impl Expression {
+    pub fn lids(self) -> Lids {
+        let (upper, lower) = match self {
+            Expression::Neutral => (Lid::RELAXED, Lid::RELAXED),
+            Expression::Surprised => (Lid::new(1.25, 0.0, 1.1), Lid::new(1.2, 0.0, 1.0)),
+            // a heavy flat upper lid
+            Expression::Sleepy => (Lid::new(0.35, 0.0, 0.3), Lid::new(0.9, 0.0, 1.0)),
+            // the upper lid slants down across the eye
+            Expression::Angry => (Lid::new(0.65, -0.5, 0.2), Lid::new(0.85, 0.0, 1.0)),
+            // a squint with both lids flattened
+            Expression::Suspicious => (Lid::new(0.45, 0.0, 0.0), Lid::new(0.5, 0.0, 0.0)),
+        };
+        Lids { upper, lower }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Expression, String> {
+        match name {
+            "neutral" => Ok(Expression::Neutral),
+            "surprised" => Ok(Expression::Surprised),
+            "sleepy" => Ok(Expression::Sleepy),
+            "angry" => Ok(Expression::Angry),
+            "suspicious" => Ok(Expression::Suspicious),
+            _ => Err(format!("no expression called {}", name)),
+        }
+    }
+}
+
+// Eases from wherever the lids are to the next expression. A new expression
+// in the middle of a transition starts from the lids as they are then.
+pub struct ExpressionState {
+    from: Lids,
+    to: Lids,
+    start: f64,
+    duration: f64,
+}
+
+impl ExpressionState {
+    pub fn new() -> ExpressionState {
+        ExpressionState {
+            from: Lids::NEUTRAL,
+            to: Lids::NEUTRAL,
+            start: 0.0,
+            duration: 0.0,
+        }
+    }
+
+    pub fn set(&mut self, expression: Expression, time: f64, duration: f64) {
+        self.from = self.lids(time);
+        self.to = expression.lids();
+        self.start = time;
+        self.duration = duration;
+    }
+
+    pub fn lids(&self, time: f64) -> Lids {
+        let t = time - self.start;
+        if t >= self.duration {
+            return self.to;
+        }
+        let t = ease::sine::ease_in_out(t, 0.0, 1.0, self.duration) as f32;
+        self.from.lerp(&self.to, t)
+    }
+}
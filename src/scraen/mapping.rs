@@ -0,0 +1,475 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+// How the LEDs of a panel are chained, compiled into a table that says for
+// every byte slot on the wire which pixel of the (already rotated) panel image
+// goes there. Pixels are indexed row major from the top left, y * w + x.
+//
+// in a config file a table with one of `lines`, `tiled` or `table`, see
+// MappingTable
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "MappingTable", into = "MappingTable")]
+pub enum PixelMapping {
+    Lines(Lines),
+    // a panel built from identical modules, chained module by module in
+    // `chain` order and wired inside each module in `module` order
+    Tiled {
+        module_wh: (u32, u32),
+        module: Lines,
+        chain: Lines,
+    },
+    // lookup table file, see `load_table`
+    Table(Cow<'static, str>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Lines {
+    pub major: Major,
+    pub serpentine: Serpentine,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    // blank slots sent between two lines
+    pub gap: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Major {
+    Rows,
+    Columns,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Serpentine {
+    Off,
+    // lines 0, 2, 4.. run backwards
+    EvenReversed,
+    // lines 1, 3, 5.. run backwards
+    OddReversed,
+}
+
+impl Lines {
+    pub const ROW_MAJOR: Lines = Lines {
+        major: Major::Rows,
+        serpentine: Serpentine::Off,
+        flip_x: false,
+        flip_y: false,
+        gap: 0,
+    };
+
+    pub const COLUMN_MAJOR: Lines = Lines {
+        major: Major::Columns,
+        ..Lines::ROW_MAJOR
+    };
+
+    // positions in a w x h grid in wiring order, None for gap slots
+    fn order(&self, (w, h): (u32, u32)) -> Vec<Option<(u32, u32)>> {
+        let (lines, line_len) = match self.major {
+            Major::Rows => (h, w),
+            Major::Columns => (w, h),
+        };
+
+        let mut order = Vec::new();
+        for line in 0..lines {
+            if line > 0 {
+                order.extend((0..self.gap).map(|_| None));
+            }
+            let reversed = match self.serpentine {
+                Serpentine::Off => false,
+                Serpentine::EvenReversed => line % 2 == 0,
+                Serpentine::OddReversed => line % 2 == 1,
+            };
+            for i in 0..line_len {
+                let i = if reversed { line_len - 1 - i } else { i };
+                let (mut x, mut y) = match self.major {
+                    Major::Rows => (i, line),
+                    Major::Columns => (line, i),
+                };
+                if self.flip_x {
+                    x = w - 1 - x;
+                }
+                if self.flip_y {
+                    y = h - 1 - y;
+                }
+                order.push(Some((x, y)));
+            }
+        }
+        order
+    }
+}
+
+// toml 0.5 only reads enums from inline tables, and writes the mapping of a
+// saved config as a [scraens.mapping.tiled] section, so the mapping goes
+// through a plain struct that reads the same either way
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct MappingTable {
+    lines: Option<Lines>,
+    tiled: Option<Tiled>,
+    table: Option<Cow<'static, str>>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Tiled {
+    module_wh: (u32, u32),
+    module: Lines,
+    chain: Lines,
+}
+
+impl TryFrom<MappingTable> for PixelMapping {
+    type Error = String;
+
+    fn try_from(table: MappingTable) -> Result<PixelMapping, String> {
+        match table {
+            MappingTable {
+                lines: Some(lines),
+                tiled: None,
+                table: None,
+            } => Ok(PixelMapping::Lines(lines)),
+            MappingTable {
+                lines: None,
+                tiled: Some(tiled),
+                table: None,
+            } => Ok(PixelMapping::Tiled {
+                module_wh: tiled.module_wh,
+                module: tiled.module,
+                chain: tiled.chain,
+            }),
+            MappingTable {
+                lines: None,
+                tiled: None,
+                table: Some(path),
+            } => Ok(PixelMapping::Table(path)),
+            _ => Err("a mapping has exactly one of lines, tiled or table".to_owned()),
+        }
+    }
+}
+
+impl From<PixelMapping> for MappingTable {
+    fn from(mapping: PixelMapping) -> MappingTable {
+        let mut table = MappingTable {
+            lines: None,
+            tiled: None,
+            table: None,
+        };
+        match mapping {
+            PixelMapping::Lines(lines) => table.lines = Some(lines),
+            PixelMapping::Tiled {
+                module_wh,
+                module,
+                chain,
+            } => {
+                table.tiled = Some(Tiled {
+                    module_wh,
+                    module,
+                    chain,
+                })
+            }
+            PixelMapping::Table(path) => table.table = Some(path),
+        }
+        table
+    }
+}
+
+// fields left out of a config file fall back to plain row major
+impl Default for Lines {
+    fn default() -> Lines {
+        Lines::ROW_MAJOR
+    }
+}
+
+impl PixelMapping {
+    pub fn table(&self, (w, h): (u32, u32)) -> Result<Vec<Option<usize>>> {
+        let index = |(x, y): (u32, u32)| (y * w + x) as usize;
+
+        match self {
+            PixelMapping::Lines(lines) => Ok(lines
+                .order((w, h))
+                .into_iter()
+                .map(|slot| slot.map(index))
+                .collect()),
+
+            PixelMapping::Tiled {
+                module_wh: (mw, mh),
+                module,
+                chain,
+            } => {
+                if *mw == 0 || *mh == 0 || w % mw != 0 || h % mh != 0 {
+                    return Err(anyhow!(
+                        "{}x{} modules don't tile a {}x{} panel",
+                        mw,
+                        mh,
+                        w,
+                        h
+                    ));
+                }
+                let inside = module.order((*mw, *mh));
+                let mut table = Vec::new();
+                for tile in chain.order((w / mw, h / mh)) {
+                    match tile {
+                        Some((tx, ty)) => table.extend(
+                            inside
+                                .iter()
+                                .map(|slot| slot.map(|(x, y)| index((tx * mw + x, ty * mh + y)))),
+                        ),
+                        None => table.extend(inside.iter().map(|_| None)),
+                    }
+                }
+                Ok(table)
+            }
+
+            PixelMapping::Table(path) => load_table(path, (w, h)),
+        }
+    }
+}
+
+// A table file lists one entry per slot on the wire, in wiring order,
+// separated by whitespace or commas. Each entry is the row major index of the
+// pixel to send there, or -1 for a blank slot. Every pixel has to be listed
+// exactly once. Everything after a # is a comment.
+//
+//   # 2x2 panel snaking from the top right
+//   1 0
+//   2 3
+pub fn load_table(path: &str, wh: (u32, u32)) -> Result<Vec<Option<usize>>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("reading pixel map {}", path))?;
+    parse_table(&text, path, wh)
+}
+
+fn parse_table(text: &str, path: &str, (w, h): (u32, u32)) -> Result<Vec<Option<usize>>> {
+    let pixel_count = (w * h) as usize;
+    let mut seen = vec![false; pixel_count];
+    let mut table = Vec::new();
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        for entry in line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|e| !e.is_empty())
+        {
+            let value: i64 = entry.parse().with_context(|| {
+                format!("{}:{}: `{}` is not a number", path, line_number + 1, entry)
+            })?;
+            let slot = match value {
+                -1 => None,
+                v if v >= 0 && (v as usize) < pixel_count => Some(v as usize),
+                v => {
+                    return Err(anyhow!(
+                        "{}:{}: pixel {} is outside the {}x{} panel",
+                        path,
+                        line_number + 1,
+                        v,
+                        w,
+                        h
+                    ))
+                }
+            };
+            if let Some(pixel) = slot {
+                if seen[pixel] {
+                    return Err(anyhow!(
+                        "{}:{}: pixel {} is listed twice",
+                        path,
+                        line_number + 1,
+                        pixel
+                    ));
+                }
+                seen[pixel] = true;
+            }
+            table.push(slot);
+        }
+    }
+    if let Some(missing) = seen.iter().position(|s| !s) {
+        return Err(anyhow!("{}: pixel {} is never sent", path, missing));
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(major: Major, serpentine: Serpentine) -> PixelMapping {
+        PixelMapping::Lines(Lines {
+            major,
+            serpentine,
+            ..Lines::ROW_MAJOR
+        })
+    }
+
+    fn table(mapping: PixelMapping, wh: (u32, u32)) -> Vec<Option<usize>> {
+        mapping.table(wh).unwrap()
+    }
+
+    fn all(pixels: &[usize]) -> Vec<Option<usize>> {
+        pixels.iter().map(|p| Some(*p)).collect()
+    }
+
+    // a 3x2 panel, pixels 0 1 2 on top and 3 4 5 below
+    #[test]
+    fn rows() {
+        let wh = (3, 2);
+        assert_eq!(
+            table(lines(Major::Rows, Serpentine::Off), wh),
+            all(&[0, 1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            table(lines(Major::Rows, Serpentine::EvenReversed), wh),
+            all(&[2, 1, 0, 3, 4, 5])
+        );
+        assert_eq!(
+            table(lines(Major::Rows, Serpentine::OddReversed), wh),
+            all(&[0, 1, 2, 5, 4, 3])
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let wh = (3, 2);
+        assert_eq!(
+            table(lines(Major::Columns, Serpentine::Off), wh),
+            all(&[0, 3, 1, 4, 2, 5])
+        );
+        assert_eq!(
+            table(lines(Major::Columns, Serpentine::EvenReversed), wh),
+            all(&[3, 0, 1, 4, 5, 2])
+        );
+        assert_eq!(
+            table(lines(Major::Columns, Serpentine::OddReversed), wh),
+            all(&[0, 3, 4, 1, 2, 5])
+        );
+    }
+
+    #[test]
+    fn gap_between_lines() {
+        let mapping = PixelMapping::Lines(Lines {
+            gap: 2,
+            ..Lines::ROW_MAJOR
+        });
+        assert_eq!(
+            table(mapping, (2, 2)),
+            vec![Some(0), Some(1), None, None, Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn flips() {
+        let flipped = |flip_x, flip_y| {
+            PixelMapping::Lines(Lines {
+                flip_x,
+                flip_y,
+                ..Lines::ROW_MAJOR
+            })
+        };
+        let wh = (3, 2);
+        assert_eq!(table(flipped(true, false), wh), all(&[2, 1, 0, 5, 4, 3]));
+        assert_eq!(table(flipped(false, true), wh), all(&[3, 4, 5, 0, 1, 2]));
+        assert_eq!(table(flipped(true, true), wh), all(&[5, 4, 3, 2, 1, 0]));
+
+        let columns = PixelMapping::Lines(Lines {
+            flip_x: true,
+            ..Lines::COLUMN_MAJOR
+        });
+        assert_eq!(table(columns, wh), all(&[2, 5, 1, 4, 0, 3]));
+    }
+
+    // a 4x2 panel of two 2x2 modules side by side
+    #[test]
+    fn tiled() {
+        let tiled = |module| PixelMapping::Tiled {
+            module_wh: (2, 2),
+            module,
+            chain: Lines::ROW_MAJOR,
+        };
+        let wh = (4, 2);
+        assert_eq!(
+            table(tiled(Lines::ROW_MAJOR), wh),
+            all(&[0, 1, 4, 5, 2, 3, 6, 7])
+        );
+        let snaking = Lines {
+            serpentine: Serpentine::OddReversed,
+            ..Lines::ROW_MAJOR
+        };
+        assert_eq!(table(tiled(snaking), wh), all(&[0, 1, 5, 4, 2, 3, 7, 6]));
+
+        let chain_flipped = PixelMapping::Tiled {
+            module_wh: (2, 2),
+            module: Lines::ROW_MAJOR,
+            chain: Lines {
+                flip_x: true,
+                ..Lines::ROW_MAJOR
+            },
+        };
+        assert_eq!(table(chain_flipped, wh), all(&[2, 3, 6, 7, 0, 1, 4, 5]));
+    }
+
+    #[test]
+    fn tiled_modules_have_to_fit() {
+        let tiled = PixelMapping::Tiled {
+            module_wh: (3, 2),
+            module: Lines::ROW_MAJOR,
+            chain: Lines::ROW_MAJOR,
+        };
+        assert!(tiled.table((4, 2)).is_err());
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let mappings = [
+            lines(Major::Columns, Serpentine::EvenReversed),
+            PixelMapping::Tiled {
+                module_wh: (8, 8),
+                module: Lines::COLUMN_MAJOR,
+                chain: Lines::ROW_MAJOR,
+            },
+            PixelMapping::Table("maps/panel.txt".into()),
+        ];
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        struct Panel {
+            mapping: PixelMapping,
+        }
+        for mapping in mappings {
+            let panel = Panel { mapping };
+            let text = toml::to_string(&panel).unwrap();
+            assert_eq!(toml::from_str::<Panel>(&text).unwrap(), panel, "{}", text);
+        }
+
+        let inline = "mapping = { lines = { serpentine = \"even_reversed\", gap = 1 } }";
+        let expected = PixelMapping::Lines(Lines {
+            serpentine: Serpentine::EvenReversed,
+            gap: 1,
+            ..Lines::ROW_MAJOR
+        });
+        assert_eq!(toml::from_str::<Panel>(inline).unwrap().mapping, expected);
+        let both = "mapping = { lines = {}, table = \"panel.txt\" }";
+        assert!(toml::from_str::<Panel>(both).is_err());
+    }
+
+    #[test]
+    fn table_file() {
+        let text = "# snaking from the top right\n1 0\n2, 3, -1\n";
+        assert_eq!(
+            parse_table(text, "test", (2, 2)).unwrap(),
+            vec![Some(1), Some(0), Some(2), Some(3), None]
+        );
+    }
+
+    #[test]
+    fn table_missing_pixel() {
+        assert!(parse_table("0 1 2", "test", (2, 2)).is_err());
+    }
+
+    #[test]
+    fn table_duplicate_pixel() {
+        assert!(parse_table("0 1 1 3", "test", (2, 2)).is_err());
+    }
+
+    #[test]
+    fn table_pixel_outside() {
+        assert!(parse_table("0 1 2 4", "test", (2, 2)).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use std::convert::TryFrom;
+
+use image::DynamicImage;
+use nannou::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// How a panel is mounted, as seen by someone standing in front of it. The
+// eye is always drawn upright and turned into the panel's own frame on the
+// way out, so a panel hung upside down still shows an upright eye looking
+// the right way.
+//
+// the mirror is applied in the panel's frame, before it is turned by
+// `rotation`, which is clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub mirror_x: bool,
+    pub mirror_y: bool,
+}
+
+// written as degrees in a config file
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum Rotation {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl Rotation {
+    pub fn degrees(self) -> u32 {
+        match self {
+            Rotation::R0 => 0,
+            Rotation::R90 => 90,
+            Rotation::R180 => 180,
+            Rotation::R270 => 270,
+        }
+    }
+
+    // the next quarter turn clockwise
+    pub fn next(self) -> Rotation {
+        match self {
+            Rotation::R0 => Rotation::R90,
+            Rotation::R90 => Rotation::R180,
+            Rotation::R180 => Rotation::R270,
+            Rotation::R270 => Rotation::R0,
+        }
+    }
+
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Rotation::R90 | Rotation::R270)
+    }
+}
+
+impl TryFrom<u32> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u32) -> Result<Rotation, String> {
+        match degrees {
+            0 => Ok(Rotation::R0),
+            90 => Ok(Rotation::R90),
+            180 => Ok(Rotation::R180),
+            270 => Ok(Rotation::R270),
+            d => Err(format!("rotation has to be 0, 90, 180 or 270, got {}", d)),
+        }
+    }
+}
+
+impl From<Rotation> for u32 {
+    fn from(rotation: Rotation) -> u32 {
+        rotation.degrees()
+    }
+}
+
+impl Orientation {
+    pub const UPRIGHT: Orientation = Orientation {
+        rotation: Rotation::R0,
+        mirror_x: false,
+        mirror_y: false,
+    };
+
+    // the upright image as the panel has to show it: turned back against
+    // the mount, then mirrored
+    pub fn to_panel(&self, image: DynamicImage) -> DynamicImage {
+        let image = match self.rotation {
+            Rotation::R0 => image,
+            Rotation::R90 => image.rotate270(),
+            Rotation::R180 => image.rotate180(),
+            Rotation::R270 => image.rotate90(),
+        };
+        let image = if self.mirror_x { image.fliph() } else { image };
+        if self.mirror_y {
+            image.flipv()
+        } else {
+            image
+        }
+    }
+
+    // a point or direction in the upright drawing, in the panel's frame
+    pub fn world_to_panel(&self, v: Vec2) -> Vec2 {
+        self.mirror(Mat2::from_angle(-self.angle()) * v)
+    }
+
+    // -1 on the mirrored axes
+    pub fn mirror_signs(&self) -> Vec2 {
+        vec2(
+            if self.mirror_x { -1.0 } else { 1.0 },
+            if self.mirror_y { -1.0 } else { 1.0 },
+        )
+    }
+
+    // turn from the panel's frame to the window. y points up in the window,
+    // so a clockwise mount is a negative angle
+    pub fn angle(&self) -> f32 {
+        -(self.rotation.degrees() as f32).to_radians()
+    }
+
+    fn mirror(&self, v: Vec2) -> Vec2 {
+        v * self.mirror_signs()
+    }
+
+    // size of the panel in the window, w and h swap on a quarter turn
+    pub fn footprint(&self, wh: (f32, f32)) -> (f32, f32) {
+        if self.rotation.swaps_axes() {
+            (wh.1, wh.0)
+        } else {
+            wh
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    const ROTATIONS: [Rotation; 4] = [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270];
+
+    fn every_orientation() -> Vec<Orientation> {
+        let mut all = Vec::new();
+        for rotation in ROTATIONS {
+            for (mirror_x, mirror_y) in [(false, false), (true, false), (false, true), (true, true)]
+            {
+                all.push(Orientation {
+                    rotation,
+                    mirror_x,
+                    mirror_y,
+                });
+            }
+        }
+        all
+    }
+
+    // 3x2, every pixel different
+    //   0 1 2
+    //   3 4 5
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(3, 2, |x, y| Luma([(y * 3 + x) as u8])))
+    }
+
+    fn pixels(image: &DynamicImage) -> ((u32, u32), Vec<u8>) {
+        let luma = image.to_luma8();
+        (luma.dimensions(), luma.into_raw())
+    }
+
+    fn turned(rotation: Rotation, mirror_x: bool, mirror_y: bool) -> ((u32, u32), Vec<u8>) {
+        let orientation = Orientation {
+            rotation,
+            mirror_x,
+            mirror_y,
+        };
+        pixels(&orientation.to_panel(image()))
+    }
+
+    #[test]
+    fn to_panel_corners() {
+        assert_eq!(
+            turned(Rotation::R0, false, false),
+            ((3, 2), vec![0, 1, 2, 3, 4, 5])
+        );
+        // mounted a quarter turn clockwise, so the panel gets it a quarter turn back
+        assert_eq!(
+            turned(Rotation::R90, false, false),
+            ((2, 3), vec![2, 5, 1, 4, 0, 3])
+        );
+        assert_eq!(
+            turned(Rotation::R180, false, false),
+            ((3, 2), vec![5, 4, 3, 2, 1, 0])
+        );
+        assert_eq!(
+            turned(Rotation::R270, false, false),
+            ((2, 3), vec![3, 0, 4, 1, 5, 2])
+        );
+        assert_eq!(
+            turned(Rotation::R0, true, false),
+            ((3, 2), vec![2, 1, 0, 5, 4, 3])
+        );
+        assert_eq!(
+            turned(Rotation::R0, false, true),
+            ((3, 2), vec![3, 4, 5, 0, 1, 2])
+        );
+        // mirrored after turning back
+        assert_eq!(
+            turned(Rotation::R90, true, false),
+            ((2, 3), vec![5, 2, 4, 1, 3, 0])
+        );
+    }
+
+    // mounting the panel undoes to_panel: mirror in the panel's frame, then
+    // turn clockwise
+    #[test]
+    fn to_panel_round_trips() {
+        for orientation in every_orientation() {
+            let panel = orientation.to_panel(image());
+            let panel = if orientation.mirror_y {
+                panel.flipv()
+            } else {
+                panel
+            };
+            let panel = if orientation.mirror_x {
+                panel.fliph()
+            } else {
+                panel
+            };
+            let mounted = match orientation.rotation {
+                Rotation::R0 => panel,
+                Rotation::R90 => panel.rotate90(),
+                Rotation::R180 => panel.rotate180(),
+                Rotation::R270 => panel.rotate270(),
+            };
+            assert_eq!(pixels(&mounted), pixels(&image()), "{:?}", orientation);
+        }
+    }
+
+    // world_to_panel moves points the same way to_panel moves pixels
+    #[test]
+    fn world_to_panel_matches_to_panel() {
+        let (w, h) = (3.0, 2.0);
+        for orientation in every_orientation() {
+            let (panel_wh, panel) = pixels(&orientation.to_panel(image()));
+            let (pw, ph) = (panel_wh.0 as f32, panel_wh.1 as f32);
+            assert_eq!((pw, ph), orientation.footprint((w, h)));
+
+            for y in 0..2 {
+                for x in 0..3 {
+                    // pixel centres around the middle, y up
+                    let v = vec2(x as f32 + 0.5 - w / 2.0, h / 2.0 - y as f32 - 0.5);
+                    let p = orientation.world_to_panel(v);
+                    let (px, py) = (
+                        (p.x + pw / 2.0 - 0.5).round(),
+                        (ph / 2.0 - p.y - 0.5).round(),
+                    );
+                    let at = py as usize * panel_wh.0 as usize + px as usize;
+                    assert_eq!(
+                        panel[at],
+                        (y * 3 + x) as u8,
+                        "{:?} pixel {},{}",
+                        orientation,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn world_to_panel_directions() {
+        let right = vec2(1.0, 0.0);
+        let turned = |rotation, mirror_x, v| {
+            Orientation {
+                rotation,
+                mirror_x,
+                mirror_y: false,
+            }
+            .world_to_panel(v)
+        };
+        assert!(turned(Rotation::R0, false, right).abs_diff_eq(vec2(1.0, 0.0), 1e-6));
+        // the panel's up points right in the window
+        assert!(turned(Rotation::R90, false, right).abs_diff_eq(vec2(0.0, 1.0), 1e-6));
+        assert!(turned(Rotation::R180, false, right).abs_diff_eq(vec2(-1.0, 0.0), 1e-6));
+        assert!(turned(Rotation::R270, false, right).abs_diff_eq(vec2(0.0, -1.0), 1e-6));
+        assert!(turned(Rotation::R0, true, vec2(1.0, 2.0)).abs_diff_eq(vec2(-1.0, 2.0), 1e-6));
+        assert!(turned(Rotation::R90, true, vec2(0.0, 1.0)).abs_diff_eq(vec2(1.0, 0.0), 1e-6));
+    }
+
+    #[test]
+    fn footprint_swaps_on_quarter_turns() {
+        for rotation in ROTATIONS {
+            let orientation = Orientation {
+                rotation,
+                ..Orientation::UPRIGHT
+            };
+            let expected = if rotation.swaps_axes() {
+                (2.0, 3.0)
+            } else {
+                (3.0, 2.0)
+            };
+            assert_eq!(
+                orientation.footprint((3.0, 2.0)),
+                expected,
+                "{:?}",
+                rotation
+            );
+        }
+    }
+
+    #[test]
+    fn mirror_signs() {
+        for orientation in every_orientation() {
+            let signs = orientation.mirror_signs();
+            assert_eq!(signs.x, if orientation.mirror_x { -1.0 } else { 1.0 });
+            assert_eq!(signs.y, if orientation.mirror_y { -1.0 } else { 1.0 });
+        }
+    }
+}
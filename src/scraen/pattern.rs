@@ -0,0 +1,105 @@
+use image::{DynamicImage, ImageBuffer, Rgba};
+use serde::{Deserialize, Serialize};
+
+// how long the moving patterns stay on one pixel, row or column
+const STEP_SEC: f64 = 0.1;
+// the checkerboard swaps over this often, so every pixel gets lit
+const CHECKER_SEC: f64 = 1.0;
+
+// 3x5 digits, one bit per pixel, top row in the high bits
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_010_010_010,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+// Patterns for commissioning a panel, drawn in place of the eye at the
+// panel's own resolution. They are drawn upright like the eye, so what shows
+// on the panel checks the orientation, the pixel mapping and the tone
+// settings together: the chase starts top left and reads left to right.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TestPattern {
+    // one pixel at a time in reading order
+    Chase,
+    // one row at a time, top to bottom
+    Rows,
+    // one column at a time, left to right
+    Columns,
+    // black on the left to white on the right
+    Gradient,
+    Checkerboard,
+    // the panel's index in the config
+    Index,
+    // every pixel on, so the tone's brightness is the limit
+    White,
+    // every pixel off
+    Blank,
+}
+
+impl TestPattern {
+    pub fn render(self, (w, h): (u32, u32), index: usize, time: f64) -> DynamicImage {
+        let step = (time / STEP_SEC) as u32;
+        let digits = digit_mask((w, h), index);
+
+        let image = ImageBuffer::from_fn(w, h, |x, y| {
+            let on = match self {
+                TestPattern::Chase => y * w + x == step % (w * h),
+                TestPattern::Rows => y == step % h,
+                TestPattern::Columns => x == step % w,
+                TestPattern::Gradient => {
+                    let v = (x * 255 / (w - 1).max(1)) as u8;
+                    return Rgba([v, v, v, 255]);
+                }
+                TestPattern::Checkerboard => (x + y + (time / CHECKER_SEC) as u32).is_multiple_of(2),
+                TestPattern::Index => digits[(y * w + x) as usize],
+                TestPattern::White => true,
+                TestPattern::Blank => false,
+            };
+            let v = if on { 255 } else { 0 };
+            Rgba([v, v, v, 255])
+        });
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+// the index in 3x5 digits, as big as fits and centred. panels too small for
+// the digits show index + 1 pixels in reading order instead
+fn digit_mask((w, h): (u32, u32), index: usize) -> Vec<bool> {
+    let text: Vec<u16> = index
+        .to_string()
+        .bytes()
+        .map(|d| DIGITS[(d - b'0') as usize])
+        .collect();
+    let text_w = text.len() as u32 * 4 - 1;
+
+    if text_w > w || 5 > h {
+        return (0..w * h).map(|i| (i as usize) <= index).collect();
+    }
+
+    let s = (w / text_w).min(h / 5);
+    let left = (w - text_w * s) / 2;
+    let top = (h - 5 * s) / 2;
+    let mut mask = vec![false; (w * h) as usize];
+    for y in 0..5 * s {
+        for x in 0..text_w * s {
+            let (col, row) = (x / s, y / s);
+            // every fourth column is the gap between digits
+            if col % 4 == 3 {
+                continue;
+            }
+            let bit = 14 - (row * 3 + col % 4);
+            if text[(col / 4) as usize] >> bit & 1 == 1 {
+                mask[((top + y) * w + left + x) as usize] = true;
+            }
+        }
+    }
+    mask
+}
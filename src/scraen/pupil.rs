@@ -0,0 +1,83 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vision::Scene;
+
+// How a panel's pupil reacts to what the camera sees. Each curve maps its
+// input to an amount of dilation, 0.0 fully contracted to 1.0 fully dilated,
+// and the two are added. The style decides what fully dilated looks like.
+//
+//   pupil = { light = [[0.0, 1.0], [0.6, 0.0]], face = [[0.1, 0.0], [0.5, 0.4]] }
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PupilDim {
+    // from the average luminance of the camera frame, 0.0 to 1.0
+    pub light: Curve,
+    // from the height of the nearest face as a fraction of the frame, so
+    // someone walking up makes the eye take an interest
+    pub face: Curve,
+    // seconds to get most of the way there, pupils close faster than they open
+    pub constrict_sec: f32,
+    pub dilate_sec: f32,
+}
+
+// Straight lines between (input, output) points sorted by input, flat past
+// the first and last point.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Curve(pub Cow<'static, [(f32, f32)]>);
+
+impl Curve {
+    pub fn at(&self, x: f32) -> f32 {
+        let points = &self.0;
+        match (points.first(), points.last()) {
+            // NaN from a zero sized face or a missing light reading
+            (Some(first), _) if x.is_nan() || x <= first.0 => first.1,
+            (_, Some(last)) if x >= last.0 => last.1,
+            (None, _) => 0.0,
+            _ => {
+                let i = points.iter().position(|p| p.0 > x).unwrap();
+                let (a, b) = (points[i - 1], points[i]);
+                a.1 + (b.1 - a.1) * (x - a.0) / (b.0 - a.0)
+            }
+        }
+    }
+
+    // in order and with a gap between each input, so `at` never divides by zero
+    pub fn is_valid(&self) -> bool {
+        self.0.windows(2).all(|w| w[0].0 < w[1].0)
+    }
+}
+
+pub struct Pupil {
+    dim: PupilDim,
+    pub val: f32,
+    last_time: Option<f64>,
+}
+
+impl Pupil {
+    pub fn new(dim: PupilDim) -> Pupil {
+        Pupil {
+            dim,
+            val: 0.5,
+            last_time: None,
+        }
+    }
+
+    pub fn update(&mut self, scene: &Scene, time: f64) {
+        let target =
+            (self.dim.light.at(scene.luminance) + self.dim.face.at(scene.face_size)).clamp(0.0, 1.0);
+        let dt = time - self.last_time.unwrap_or(time);
+        self.last_time = Some(time);
+
+        let sec = if target < self.val {
+            self.dim.constrict_sec
+        } else {
+            self.dim.dilate_sec
+        };
+        // eases out towards the target whatever the frame rate
+        let k = 1.0 - (-dt as f32 / sec.max(0.001)).exp();
+        self.val += (target - self.val) * k;
+    }
+}
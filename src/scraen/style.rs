@@ -0,0 +1,109 @@
+use nannou::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub mod anatomical;
+use anatomical::{Anatomical, AnatomicalDim};
+
+use super::expression::Lids;
+
+// What a style needs to draw one frame of the eye, in fbo pixels with the
+// origin in the middle of the panel.
+pub struct EyeState {
+    pub xy: Vec2,
+    // distance and angle of xy from the middle
+    pub rt: Vec2,
+    // radius of the eye
+    pub r: f32,
+    // the fbo's size, and how many fbo pixels make one panel pixel
+    pub size: Vec2,
+    pub pixel: f32,
+    // 0.0 open to 1.0 shut
+    pub blink: f32,
+    // 0.0 contracted to 1.0 dilated, see PupilDim
+    pub pupil: f32,
+    // the expression, the blink closes them on top of it
+    pub lids: Lids,
+    pub time: f64,
+}
+
+// Draws the eye into a panel's fbo. A style clears the background itself.
+pub trait EyeStyle {
+    fn draw(&mut self, draw: &Draw, eye: &EyeState);
+}
+
+// Picks a panel's style in the config, `style = { type = "arrow" }`. The
+// anatomical eye takes its settings next to the type.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StyleDim {
+    // a white disc with square lids
+    #[default]
+    Disc,
+    // a line through the middle, pointing where the eye looks
+    Arrow,
+    // a random letter in place of the disc
+    Glyph,
+    // sclera, iris, pupil and curved lids, for the bigger panels
+    Anatomical(AnatomicalDim),
+}
+
+pub fn build_style(dim: &StyleDim) -> Box<dyn EyeStyle> {
+    match dim {
+        StyleDim::Disc => Box::new(Disc),
+        StyleDim::Arrow => Box::new(Arrow),
+        StyleDim::Glyph => Box::new(Glyph),
+        StyleDim::Anatomical(dim) => Box::new(Anatomical::new(dim.clone())),
+    }
+}
+
+pub struct Disc;
+pub struct Arrow;
+pub struct Glyph;
+
+impl EyeStyle for Disc {
+    fn draw(&mut self, draw: &Draw, eye: &EyeState) {
+        draw.background().color(BLACK);
+        draw.ellipse().xy(eye.xy).radius(eye.r).color(WHITE);
+        draw_lids(draw, eye);
+    }
+}
+
+impl EyeStyle for Arrow {
+    fn draw(&mut self, draw: &Draw, eye: &EyeState) {
+        let (rad, angle) = (eye.rt.x, eye.rt.y);
+        let start = vec2(rad * angle.cos(), rad * angle.sin());
+        let end = -start;
+
+        draw.background().color(BLACK);
+        draw.arrow().start(start).end(end).weight(2.0).color(WHITE);
+        draw_lids(draw, eye);
+    }
+}
+
+impl EyeStyle for Glyph {
+    fn draw(&mut self, draw: &Draw, eye: &EyeState) {
+        draw.background().color(BLACK);
+        draw.text(std::str::from_utf8(&[random_ascii() as u8]).unwrap())
+            .xy(eye.xy)
+            .font_size(24)
+            .color(WHITE);
+        draw_lids(draw, eye);
+    }
+}
+
+// two black rects closing in from above and below as the eye blinks. these
+// only follow the openness of the expression, not its tilt or curve
+fn draw_lids(draw: &Draw, eye: &EyeState) {
+    let closed = |openness: f32| (1.0 - openness.min(1.0) * (1.0 - eye.blink)).max(0.0);
+    let upper = eye.r * closed(eye.lids.upper.openness);
+    let lower = eye.r * closed(eye.lids.lower.openness);
+
+    draw.rect()
+        .xy(eye.xy + vec2(0.0, eye.r - upper / 2.0))
+        .w_h(eye.r * 2.0, upper)
+        .color(BLACK);
+    draw.rect()
+        .xy(eye.xy - vec2(0.0, eye.r - lower / 2.0))
+        .w_h(eye.r * 2.0, lower)
+        .color(BLACK);
+}
@@ -0,0 +1,155 @@
+use nannou::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{EyeState, EyeStyle};
+use crate::scraen::expression::Lid;
+
+// points along each lid, enough for a smooth curve at 3x supersampling
+const LID_STEPS: usize = 24;
+// where the lids meet when shut, as a fraction of the sclera's half height
+// below the middle. real lids close low, the upper one does most of the work
+const CLOSED_LINE: f32 = -0.3;
+// relaxed lids cover a little of the sclera, so a surprised eye can open wider
+const RELAXED_OPEN: f32 = 0.8;
+// the highlight is a reflection of a light up and to the left, it moves
+// less than the iris it sits on
+const LIGHT: (f32, f32) = (-0.4, 0.4);
+const HIGHLIGHT_FOLLOW: f32 = 0.3;
+
+// Sizes are fractions so one config reads the same on every panel size.
+// Everything is laid out in the fbo, which is downsampled to the panel with a
+// smoothing filter, so the features are kept to at least a panel pixel or
+// they blur away.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AnatomicalDim {
+    // width and height of the open eye, as a fraction of the panel
+    pub sclera_wh: (f32, f32),
+    // radius, as a fraction of the sclera's half height
+    pub iris: f32,
+    // radius contracted and dilated, as a fraction of the iris
+    pub pupil: (f32, f32),
+    // how much further the pupil moves than the iris, it sits behind the
+    // bulge of the cornea
+    pub parallax: f32,
+    pub highlight: bool,
+    pub sclera_color: (u8, u8, u8),
+    pub iris_color: (u8, u8, u8),
+}
+
+impl Default for AnatomicalDim {
+    fn default() -> AnatomicalDim {
+        AnatomicalDim {
+            sclera_wh: (0.9, 0.6),
+            iris: 0.85,
+            pupil: (0.3, 0.65),
+            parallax: 0.15,
+            highlight: true,
+            sclera_color: (200, 200, 200),
+            iris_color: (90, 90, 90),
+        }
+    }
+}
+
+pub struct Anatomical {
+    dim: AnatomicalDim,
+}
+
+impl Anatomical {
+    pub fn new(dim: AnatomicalDim) -> Anatomical {
+        Anatomical { dim }
+    }
+}
+
+impl EyeStyle for Anatomical {
+    fn draw(&mut self, draw: &Draw, eye: &EyeState) {
+        let dim = &self.dim;
+        let half = vec2(dim.sclera_wh.0, dim.sclera_wh.1) * eye.size / 2.0;
+        let iris_r = (dim.iris * half.y).max(eye.pixel * 1.5);
+        let pupil = dim.pupil.0 + (dim.pupil.1 - dim.pupil.0) * eye.pupil;
+        let pupil_r = (pupil * iris_r).max(eye.pixel * 0.75);
+
+        // how far the eye looks, 1.0 at the edge of the panel
+        let look = (eye.xy / (eye.size.min_element() / 2.0)).clamp_length_max(1.0);
+        // the iris can go until it is half hidden by the corners
+        let travel = (half - vec2(iris_r * 0.5, iris_r * 0.3)).max(Vec2::ZERO);
+        let iris_xy = look * travel;
+        let pupil_xy =
+            iris_xy + (look * iris_r * dim.parallax).clamp_length_max(iris_r - pupil_r);
+
+        draw.background().color(BLACK);
+        draw.polygon()
+            .color(rgb8(dim.sclera_color.0, dim.sclera_color.1, dim.sclera_color.2))
+            .points(lid_curve(half, 1.0).chain(lid_curve(half, -1.0).rev()));
+        draw.ellipse()
+            .xy(iris_xy)
+            .radius(iris_r)
+            .color(rgb8(dim.iris_color.0, dim.iris_color.1, dim.iris_color.2));
+        draw.ellipse().xy(pupil_xy).radius(pupil_r).color(BLACK);
+
+        if dim.highlight {
+            let xy = iris_xy * HIGHLIGHT_FOLLOW + vec2(LIGHT.0, LIGHT.1) * iris_r;
+            draw.ellipse()
+                .xy(snap_to_pixel(xy, eye))
+                .radius(eye.pixel * 0.75)
+                .color(WHITE);
+        }
+
+        draw_lids(draw, eye, half);
+    }
+}
+
+// black covers from the lid edges out to the panel's border, hiding whatever
+// of the iris sticks out of the sclera
+fn draw_lids(draw: &Draw, eye: &EyeState, half: Vec2) {
+    let open = 1.0 - eye.blink;
+    let far = eye.size.max_element();
+
+    let upper = lid_edge(half, far, &eye.lids.upper, open, 1.0);
+    draw.polygon()
+        .color(BLACK)
+        .points(upper.chain([vec2(far, far), vec2(-far, far)]));
+    let lower = lid_edge(half, far, &eye.lids.lower, open, -1.0);
+    draw.polygon()
+        .color(BLACK)
+        .points(lower.chain([vec2(far, -far), vec2(-far, -far)]));
+}
+
+// one lid's edge from -far to far, shaped by the expression and then pulled
+// towards the line the lids close on by the blink. past the corners it
+// carries on flat
+fn lid_edge(half: Vec2, far: f32, lid: &Lid, open: f32, side: f32) -> impl Iterator<Item = Vec2> {
+    let lid = *lid;
+    let y = move |x: f32| {
+        let lens = lens(x, half.x);
+        let shape = lid.curve * lens + (1.0 - lid.curve);
+        let edge = (side * lid.openness * shape + lid.tilt * x / half.x) * RELAXED_OPEN * half.y;
+        let shut = CLOSED_LINE * half.y * lens;
+        shut + (edge - shut) * open
+    };
+    let edge = lid_curve(half, side).map(move |p| vec2(p.x, y(p.x)));
+    std::iter::once(vec2(-far, y(-half.x)))
+        .chain(edge)
+        .chain(std::iter::once(vec2(far, y(half.x))))
+}
+
+// the open edge of the sclera, upper with side 1.0 and lower with -1.0
+fn lid_curve(half: Vec2, side: f32) -> impl DoubleEndedIterator<Item = Vec2> {
+    (0..=LID_STEPS).map(move |i| {
+        let x = -half.x + 2.0 * half.x * i as f32 / LID_STEPS as f32;
+        vec2(x, side * half.y * lens(x, half.x))
+    })
+}
+
+// 1.0 in the middle down to 0.0 at the corners and past them, an almond
+// rather than an ellipse so the corners come to a point
+fn lens(x: f32, half_w: f32) -> f32 {
+    (1.0 - (x / half_w).powi(2)).max(0.0)
+}
+
+// the middle of the panel pixel under xy, so a small highlight lands on one
+// pixel instead of smearing over four
+fn snap_to_pixel(xy: Vec2, eye: &EyeState) -> Vec2 {
+    let corner = eye.size / 2.0;
+    ((xy + corner) / eye.pixel).floor() * eye.pixel + eye.pixel / 2.0 - corner
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+// Output tone processing for one panel, applied to the downsampled pixels
+// before they are packed into the panel's colour format.
+//
+// values are carried as 8.8 fixed point between the gamma lut and dithering,
+// so the fraction lost when going back to 8 bits can be dithered instead of
+// showing up as steps in slow fades
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToneDim {
+    pub gamma: f32,
+    // 0.0 to 1.0, scales every channel after gamma
+    pub brightness: f32,
+    // the whole panel is dimmed when the estimated draw goes over this
+    pub current_budget_ma: Option<f32>,
+    // draw of a single channel at full on
+    pub ma_per_channel: f32,
+    pub dither: Dither,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dither {
+    Off,
+    // 4x4 bayer matrix, the pattern is fixed in space
+    Ordered,
+    // per pixel error carried to the next frame, averages out over time
+    Temporal,
+}
+
+const BAYER_4X4: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+const ONE: u32 = 256;
+const FULL: u32 = 255 * ONE;
+
+pub struct Tone {
+    dim: ToneDim,
+    lut: [u32; 256],
+    error: Vec<[u32; 3]>,
+}
+
+impl Tone {
+    pub fn new(dim: ToneDim) -> Tone {
+        let mut lut = [0; 256];
+        for (i, v) in lut.iter_mut().enumerate() {
+            let linear = (i as f32 / 255.0).powf(dim.gamma);
+            *v = (linear * FULL as f32).round() as u32;
+        }
+
+        Tone {
+            dim,
+            lut,
+            error: Vec::new(),
+        }
+    }
+
+    pub fn apply(&mut self, pixels: &mut [[u8; 3]], width: u32) {
+        let brightness = (self.dim.brightness.clamp(0.0, 1.0) * ONE as f32) as u32;
+        let mut fixed: Vec<[u32; 3]> = pixels
+            .iter()
+            .map(|p| p.map(|c| self.lut[c as usize] * brightness / ONE))
+            .collect();
+
+        if let Some(budget) = self.dim.current_budget_ma {
+            let total: u64 = fixed.iter().flatten().map(|c| *c as u64).sum();
+            let draw_ma = total as f32 / FULL as f32 * self.dim.ma_per_channel;
+            if draw_ma > budget {
+                let scale = budget / draw_ma;
+                for c in fixed.iter_mut().flatten() {
+                    *c = (*c as f32 * scale) as u32;
+                }
+            }
+        }
+
+        if self.error.len() != fixed.len() {
+            self.error = vec![[0; 3]; fixed.len()];
+        }
+
+        for (i, (out, value)) in pixels.iter_mut().zip(fixed).enumerate() {
+            let (x, y) = (i % width as usize, i / width as usize);
+            for c in 0..3 {
+                out[c] = match self.dim.dither {
+                    Dither::Off => quantize(value[c] + ONE / 2),
+                    Dither::Ordered => quantize(value[c] + BAYER_4X4[y % 4][x % 4] * 16 + 8),
+                    Dither::Temporal => {
+                        let acc = value[c] + self.error[i][c];
+                        let q = quantize(acc);
+                        self.error[i][c] = acc - q as u32 * ONE;
+                        q
+                    }
+                };
+            }
+        }
+    }
+}
+
+fn quantize(fixed: u32) -> u8 {
+    (fixed / ONE).min(255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(gamma: f32, brightness: f32, dither: Dither) -> Tone {
+        Tone::new(ToneDim {
+            gamma,
+            brightness,
+            current_budget_ma: None,
+            ma_per_channel: 20.0,
+            dither,
+        })
+    }
+
+    fn grey(v: u8, n: usize) -> Vec<[u8; 3]> {
+        vec![[v; 3]; n]
+    }
+
+    fn mean(pixels: &[[u8; 3]]) -> f32 {
+        pixels.iter().map(|p| p[0] as f32).sum::<f32>() / pixels.len() as f32
+    }
+
+    #[test]
+    fn linear_at_full_brightness_is_identity() {
+        let mut pixels: Vec<[u8; 3]> = (0..=255).map(|v| [v, v, v]).collect();
+        let expected = pixels.clone();
+        tone(1.0, 1.0, Dither::Off).apply(&mut pixels, 16);
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn ends_stay_put() {
+        for dither in [Dither::Off, Dither::Ordered, Dither::Temporal] {
+            let mut tone = tone(2.2, 1.0, dither);
+            let mut black = grey(0, 16);
+            let mut white = grey(255, 16);
+            tone.apply(&mut black, 4);
+            tone.apply(&mut white, 4);
+            assert_eq!(black, grey(0, 16));
+            assert_eq!(white, grey(255, 16));
+        }
+    }
+
+    // half brightness puts 101 at 50.5, halfway between two output levels
+    #[test]
+    fn ordered_dither_averages_over_the_frame() {
+        let mut pixels = grey(101, 16);
+        tone(1.0, 0.5, Dither::Ordered).apply(&mut pixels, 4);
+        assert_eq!(mean(&pixels), 50.5);
+    }
+
+    #[test]
+    fn temporal_dither_averages_over_frames() {
+        let mut tone = tone(1.0, 0.5, Dither::Temporal);
+        let mut total = Vec::new();
+        for _ in 0..16 {
+            let mut pixels = grey(101, 4);
+            tone.apply(&mut pixels, 2);
+            total.extend(pixels);
+        }
+        assert_eq!(mean(&total), 50.5);
+    }
+}
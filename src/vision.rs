@@ -1,18 +1,40 @@
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use crate::CAMERA_WH;
 use image::{ImageBuffer, Rgb};
-use nannou::image::DynamicImage;
+use nannou::image::{DynamicImage, GenericImageView};
 use nannou::prelude::*;
-use nokhwa::{CameraFormat, FrameFormat, ThreadedCamera};
+use nokhwa::{CameraFormat, CaptureAPIBackend, FrameFormat, ThreadedCamera};
 use rustface::{Detector, ImageData};
+use serde::{Deserialize, Serialize};
 use wgpu::Texture;
 
 const MODEL_PATH: &str = "model/seeta_fd_frontal_v1.0.bin";
-const CAMERA_WH_F32: (f32, f32) = (CAMERA_WH.0 as f32, CAMERA_WH.1 as f32);
 
 static mut CAMERA_READY: [bool; 2] = [false, false];
+
+// rustface search parameters, see constants::DETECTOR for the defaults
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DetectorDim {
+    pub min_face_size: u32,
+    pub score_thresh: f64,
+    pub pyramid_scale_factor: f32,
+    pub slide_window_step: (u32, u32),
+}
+
+// What the eyes react to besides where to look.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scene {
+    // average of the face camera's frame, 0.0 to 1.0
+    pub luminance: f32,
+    // height of the nearest face as a fraction of the frame, 0.0 without one
+    pub face_size: f32,
+}
+
+// every nth pixel is enough for an average
+const LUMINANCE_STEP: usize = 16;
+
 struct Cam {
     backend: Option<ThreadedCamera>,
     frame: Frame,
@@ -29,6 +51,8 @@ pub enum Frame {
 pub struct Vision {
     webcams: Vec<Cam>,
     detector: Arc<Mutex<AsyncDetector>>,
+    // new settings from a config reload, waiting for the detector to be free
+    pending_detector: Option<DetectorDim>,
 
     faces: Arc<Mutex<Vec<Rect>>>,
     downscale_factor: f32,
@@ -38,6 +62,8 @@ pub struct Vision {
     pub biggest_face: Rect,
 
     ping_pong: bool,
+    luminance: f32,
+    frame_h: f32,
 }
 
 impl Vision {
@@ -46,15 +72,16 @@ impl Vision {
         (w, h): (u32, u32),
         drawspace_rect: Rect,
         webcams: [usize; 2], // settings: [(usize, Rect); 2],
+        detector_dim: DetectorDim,
     ) -> Vision {
         let camera_wh = UVec2::new(w, h);
         let format = CameraFormat::new_from(
-            CAMERA_WH.0,
-            CAMERA_WH.1,
+            w,
+            h,
             FrameFormat::MJPEG,
             30,
         );
-        let img = &DynamicImage::new_rgb8(CAMERA_WH.0, CAMERA_WH.1);
+        let img = &DynamicImage::new_rgb8(w, h);
 
         let drawspace_halves = [
             Rect::from_corners(
@@ -107,21 +134,18 @@ impl Vision {
             }
         }
 
-        let mut detector_raw =
+        let detector_raw =
             rustface::create_detector(MODEL_PATH).unwrap();
 
-        detector_raw.set_min_face_size(40);
-        detector_raw.set_score_thresh(1.0);
-        detector_raw.set_pyramid_scale_factor(0.1);
-        detector_raw.set_slide_window_step(4, 4);
-
-        let detector = AsyncDetector {
+        let mut detector = AsyncDetector {
             inner: detector_raw,
         };
+        detector.configure(detector_dim);
 
         Vision {
             webcams,
             detector: Arc::new(Mutex::new(detector)),
+            pending_detector: None,
             faces: Arc::new(Mutex::new(Vec::new())),
 
             downscale_factor: 1.0,
@@ -129,11 +153,31 @@ impl Vision {
             biggest_face: Rect::from_x_y_w_h(0.0, 0.0, 0.0, 0.0),
 
             ping_pong: false,
+            luminance: 0.5,
+            // the frames are turned a quarter
+            frame_h: w as f32,
         }
     }
     pub fn initialize(&self) {}
 
+    // goes in once a detection in progress has finished, without holding
+    // up the frame
+    pub fn set_detector(&mut self, dim: DetectorDim) {
+        self.pending_detector = Some(dim);
+        self.configure_detector();
+    }
+
+    fn configure_detector(&mut self) {
+        if let Some(dim) = self.pending_detector {
+            if let Ok(mut detector) = self.detector.try_lock() {
+                detector.configure(dim);
+                self.pending_detector = None;
+            }
+        }
+    }
+
     pub fn update(&mut self, app: &App) -> Option<Vec2> {
+        self.configure_detector();
         self.update_cameras(app);
         self.update_faces();
         self.get_target()
@@ -145,6 +189,7 @@ impl Vision {
 
         if unsafe { CAMERA_READY[0] && CAMERA_READY[1] } {
             unsafe { CAMERA_READY = [false, false] } // println!("{}x{} {}", image.width(), image.height(), image.len());
+            let face_cam = self.ping_pong;
             let cam = match self.ping_pong {
                 true => &mut self.webcams[0],
                 false => &mut self.webcams[1],
@@ -154,6 +199,10 @@ impl Vision {
                 if let Ok(img) = &mut backend.poll_frame() {
                     let img =
                         DynamicImage::ImageRgb8(img.clone()).rotate270();
+                    if face_cam {
+                        self.luminance = average_luminance(&img);
+                        self.frame_h = img.height() as f32;
+                    }
                     cam.texture = Texture::from_image::<&App>(app, &img);
                     cam.frame = Frame::Unprocessd(img);
                 }
@@ -161,6 +210,17 @@ impl Vision {
         }
     }
 
+    pub fn scene(&self) -> Scene {
+        let nearest = match self.faces.lock() {
+            Ok(faces) => faces.iter().map(|f| f.h()).fold(0.0, f32::max),
+            Err(_) => 0.0,
+        };
+        Scene {
+            luminance: self.luminance,
+            face_size: nearest / self.frame_h,
+        }
+    }
+
     pub fn draw_camera(&self, draw: &Draw) {
         for cam in &self.webcams {
             let t = cam.cam_to_screen;
@@ -223,7 +283,7 @@ impl Vision {
         if let Ok(faces) = self.faces.lock() {
             let biggest_face = faces
                 .iter()
-                .min_by(|a, b| a.h().partial_cmp(&b.h()).unwrap())?;
+                .min_by(|a, b| a.h().total_cmp(&b.h()))?;
 
             self.biggest_face = *biggest_face;
         }
@@ -256,6 +316,14 @@ pub struct AsyncDetector {
 }
 
 impl AsyncDetector {
+    pub fn configure(&mut self, dim: DetectorDim) {
+        self.inner.set_min_face_size(dim.min_face_size);
+        self.inner.set_score_thresh(dim.score_thresh);
+        self.inner.set_pyramid_scale_factor(dim.pyramid_scale_factor);
+        self.inner
+            .set_slide_window_step(dim.slide_window_step.0, dim.slide_window_step.1);
+    }
+
     pub fn detect(&mut self, image: &DynamicImage) -> Vec<Rect> {
         let gray = image.to_luma8();
         let (w, h) = gray.dimensions();
@@ -278,6 +346,42 @@ impl AsyncDetector {
 }
 unsafe impl Send for AsyncDetector {}
 
+pub fn print_cameras() {
+    match nokhwa::query_devices(CaptureAPIBackend::Auto) {
+        Ok(cameras) if cameras.is_empty() => println!("no cameras found"),
+        Ok(cameras) => {
+            for camera in cameras {
+                println!(
+                    "{}  {}  {}",
+                    camera.index(),
+                    camera.human_name(),
+                    camera.description()
+                );
+            }
+        }
+        Err(err) => println!("couldn't list cameras: {}", err),
+    }
+}
+
+fn average_luminance(image: &DynamicImage) -> f32 {
+    let rgb = match image.as_rgb8() {
+        Some(rgb) => rgb,
+        None => return 0.5,
+    };
+    let (sum, count) = rgb
+        .pixels()
+        .step_by(LUMINANCE_STEP)
+        .fold((0u64, 0u64), |(sum, count), p| {
+            let [r, g, b] = p.0;
+            let luma = (2126 * r as u64 + 7152 * g as u64 + 722 * b as u64) / 10000;
+            (sum + luma, count + 1)
+        });
+    if count == 0 {
+        return 0.5;
+    }
+    sum as f32 / count as f32 / 255.0
+}
+
 pub fn get_t_xy(rect: Rect, t: Affine2) -> Point2 {
     t.transform_point2(rect.xy())
 }